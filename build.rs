@@ -0,0 +1,55 @@
+use std::env;
+use std::fs;
+use std::path::Path;
+
+// The translator, compiled on its own since a build script cannot use the
+// crate it builds, along with the instruction decoding it shares with the
+// crate. The translator only needs part of that.
+#[path = "src/intcode/aot/codegen.rs"]
+mod codegen;
+#[allow(dead_code)]
+#[path = "src/intcode/opcode/decode.rs"]
+mod decode;
+
+// Translates the programs that run natively into `OUT_DIR`, where `aot`
+// includes them from.
+fn main() {
+    let out_dir = env::var_os("OUT_DIR").expect("Should have been given an output directory");
+    let out_dir = Path::new(&out_dir);
+    println!("cargo:rerun-if-changed=build.rs");
+    println!("cargo:rerun-if-changed=src/intcode/opcode/decode.rs");
+    println!("cargo:rerun-if-changed=src/intcode/aot/codegen.rs");
+    for (name, file_path) in [
+        ("tight_loop", "inputs/tight-loop.txt"),
+        ("day7", "inputs/day7-input.txt"),
+    ] {
+        println!("cargo:rerun-if-changed={}", file_path);
+        write(out_dir, name, load(file_path));
+    }
+}
+
+// A program in the puzzle input format. The build goes on without the
+// translation of one that is missing or malformed.
+fn load(file_path: &str) -> Result<Vec<i64>, String> {
+    let contents = fs::read_to_string(file_path).map_err(|error| error.to_string())?;
+    contents
+        .trim()
+        .split(',')
+        .map(|word| word.trim().parse())
+        .collect::<Result<Vec<i64>, _>>()
+        .map_err(|error| error.to_string())
+}
+
+fn write(out_dir: &Path, name: &str, image: Result<Vec<i64>, String>) {
+    let source = match image
+        .and_then(|image| codegen::translate_image(&image).map_err(|error| error.to_string()))
+    {
+        Ok(source) => source,
+        Err(error) => {
+            println!("cargo:warning=not translating {}: {}", name, error);
+            codegen::untranslated()
+        }
+    };
+    fs::write(out_dir.join(format!("{}.rs", name)), source)
+        .expect("Should have been able to write the translation");
+}
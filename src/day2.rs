@@ -1,53 +1,93 @@
-use std::fs;
+use crate::intcode::engine::Engine;
+use crate::intcode::error::VmError;
+use crate::intcode::loader::Intcode;
+use crate::intcode::snapshot::Snapshot;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
 
-fn evaluate_program(mut program: Vec<usize>) -> (usize, usize) {
-    let mut position = 0;
-    let mut new_value;
-    let mut new_value_position;
-    let mut terminate_program = false;
-
-    while !terminate_program {
-        let opcode = program[position];
-        if opcode == 99 {
-            terminate_program = true;
-            continue;
-        }
+// Every run resumes from the same snapshot, so only the memory page holding
+// the noun and verb gets copied.
+fn evaluate_program(program: &Snapshot, noun: i64, verb: i64) -> Result<i64, VmError> {
+    let mut program_state = program.resume();
+    program_state.program.set(1, noun);
+    program_state.program.set(2, verb);
+    let mut engine = Engine::new(program_state);
+    engine.run()?;
+    Ok(engine.state().program[0])
+}
 
-        new_value_position = program[position + 3];
-        if opcode == 1 {
-            new_value = program[program[position + 1]] + program[program[position + 2]];
-            program[new_value_position] = new_value;
-            position += 4;
-        } else if opcode == 2 {
-            new_value = program[program[position + 1]] * program[program[position + 2]];
-            program[new_value_position] = new_value;
-            position += 4;
-        } else {
-            println!("Unknown opcode: {:?}", opcode);
-            terminate_program = true;
-        }
-    }
-    (program[0], program[1] * 100 + program[2])
+// Splits the nouns between threads; whichever finds the target first stops
+// the others.
+fn find_noun_verb(program: &Snapshot, target: i64) -> Option<(i64, i64)> {
+    let found = AtomicBool::new(false);
+    let threads = thread::available_parallelism().map_or(1, |n| n.get());
+    thread::scope(|scope| {
+        let handles: Vec<_> = (0..threads)
+            .map(|thread_index| {
+                let found = &found;
+                scope.spawn(move || {
+                    for noun in (thread_index as i64..100).step_by(threads) {
+                        for verb in 0..100 {
+                            if found.load(Ordering::Relaxed) {
+                                return None;
+                            }
+                            // Some inputs send the program off into invalid
+                            // opcodes, which just means they are not the answer.
+                            if evaluate_program(program, noun, verb) == Ok(target) {
+                                found.store(true, Ordering::Relaxed);
+                                return Some((noun, verb));
+                            }
+                        }
+                    }
+                    None
+                })
+            })
+            .collect();
+        handles
+            .into_iter()
+            .filter_map(|handle| handle.join().unwrap())
+            .next()
+    })
 }
 pub fn day2(file_path: String) {
-    let mut numbers: Vec<usize>;
+    let program: Intcode =
+        Intcode::from_file(file_path).expect("Should have been able to load the program");
+    let program = program.builder().build().snapshot();
+
+    println!("{:?}", evaluate_program(&program, 12, 2).unwrap());
+    let (noun, verb) = find_noun_verb(&program, 19690720).expect("No noun and verb match");
+    println!("{:?}", noun * 100 + verb);
+}
 
-    let contents = fs::read_to_string(file_path).expect("Should have been able to read the file");
+#[cfg(test)]
+mod tests {
+    use crate::day2::{evaluate_program, find_noun_verb};
+    use crate::intcode::program::ProgramState;
+    use crate::intcode::snapshot::Snapshot;
 
-    numbers = contents
-        .split(',')
-        .map(|s| s.trim())
-        .filter(|s| !s.is_empty())
-        .map(|s| s.parse().unwrap())
-        .collect();
-    for noun in 0..100 {
-        numbers[1] = noun;
-        for verb in 0..100 {
-            numbers[2] = verb;
-            let (result, answer) = evaluate_program(numbers.clone());
-            if result == 19690720 {
-                println!("{:?}", (result, answer));
-            }
-        }
+    fn program() -> Snapshot {
+        ProgramState::new(vec![
+            1, 12, 2, 3, 1, 1, 2, 3, 1, 3, 4, 3, 1, 5, 0, 3, 2, 1, 13, 19, 1, 9, 19, 23, 1, 6, 23,
+            27, 2, 27, 9, 31, 2, 6, 31, 35, 1, 5, 35, 39, 1, 10, 39, 43, 1, 43, 13, 47, 1, 47, 9,
+            51, 1, 51, 9, 55, 1, 55, 9, 59, 2, 9, 59, 63, 2, 9, 63, 67, 1, 5, 67, 71, 2, 13, 71,
+            75, 1, 6, 75, 79, 1, 10, 79, 83, 2, 6, 83, 87, 1, 87, 5, 91, 1, 91, 9, 95, 1, 95, 10,
+            99, 2, 9, 99, 103, 1, 5, 103, 107, 1, 5, 107, 111, 2, 111, 10, 115, 1, 6, 115, 119, 2,
+            10, 119, 123, 1, 6, 123, 127, 1, 127, 5, 131, 2, 9, 131, 135, 1, 5, 135, 139, 1, 139,
+            10, 143, 1, 143, 2, 147, 1, 147, 5, 0, 99, 2, 0, 14, 0,
+        ])
+        .snapshot()
+    }
+    #[test]
+    fn test_part1() {
+        assert_eq!(evaluate_program(&program(), 12, 2), Ok(5305097));
+    }
+    #[test]
+    fn test_part2() {
+        assert_eq!(find_noun_verb(&program(), 19690720), Some((49, 25)));
+        assert_eq!(find_noun_verb(&program(), 5305097), Some((12, 2)));
+    }
+    #[test]
+    fn test_unreachable_target() {
+        assert_eq!(find_noun_verb(&program(), -1), None);
     }
 }
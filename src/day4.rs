@@ -49,17 +49,17 @@ mod tests {
     use crate::day4::{check_adjacency_rule, check_no_decrease_rule};
     #[test]
     fn check_112233() {
-        assert_eq!(check_no_decrease_rule(112233), true);
-        assert_eq!(check_adjacency_rule(112233), true);
+        assert!(check_no_decrease_rule(112233));
+        assert!(check_adjacency_rule(112233));
     }
     #[test]
     fn check_123444() {
-        assert_eq!(check_no_decrease_rule(123444), true);
-        assert_eq!(check_adjacency_rule(123444), false);
+        assert!(check_no_decrease_rule(123444));
+        assert!(!check_adjacency_rule(123444));
     }
     #[test]
     fn check_111122() {
-        assert_eq!(check_no_decrease_rule(111122), true);
-        assert_eq!(check_adjacency_rule(111122), true);
+        assert!(check_no_decrease_rule(111122));
+        assert!(check_adjacency_rule(111122));
     }
 }
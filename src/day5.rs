@@ -1,39 +1,29 @@
+use crate::intcode::loader::{Intcode, ProgramBuilder};
 use crate::intcode::program::ProgramState;
-use std::fs;
+use crate::intcode::word::Word;
 
-fn evaluate_program(program: Vec<i32>, inputs: Vec<i32>) -> ProgramState {
-    let mut program_state = ProgramState {
-        program,
-        head: 0,
-        running: true,
-        inputs,
-        outputs: Vec::<i32>::new(),
-    };
-    program_state.update();
+fn evaluate_program<W: Word>(program: Vec<W>, inputs: Vec<W>) -> ProgramState<W> {
+    let mut program_state = ProgramBuilder::new(program).inputs(inputs).build();
+    program_state.run().unwrap();
     program_state
 }
 pub fn day5(file_path: String) {
-    let contents = fs::read_to_string(file_path).expect("Should have been able to read the file");
+    let program: Intcode =
+        Intcode::from_file(file_path).expect("Should have been able to load the program");
 
-    let numbers: Vec<i32> = contents
-        .split(',')
-        .map(|s| s.trim())
-        .filter(|s| !s.is_empty())
-        .map(|s| s.parse().unwrap())
-        .collect();
     println!(
         "{:?}",
-        evaluate_program(numbers, vec![1]).outputs.pop().unwrap()
+        evaluate_program(program.image.clone(), vec![1])
+            .outputs
+            .pop_back()
+            .unwrap()
     );
-    let numbers: Vec<i32> = contents
-        .split(',')
-        .map(|s| s.trim())
-        .filter(|s| !s.is_empty())
-        .map(|s| s.parse().unwrap())
-        .collect();
     println!(
         "{:?}",
-        evaluate_program(numbers, vec![5]).outputs.pop().unwrap()
+        evaluate_program(program.image, vec![5])
+            .outputs
+            .pop_back()
+            .unwrap()
     );
 }
 
@@ -150,14 +140,14 @@ mod tests {
             224, 674, 101, 1, 223, 223, 4, 223, 99, 226,
         ];
         let result = evaluate_program(program, vec![1]);
-        assert_eq!(*result.outputs.last().unwrap(), 7839346);
+        assert_eq!(*result.outputs.back().unwrap(), 7839346);
     }
     #[test]
     fn test_equal_position() {
         for value in 5..10 {
             let input = vec![3, 9, 8, 9, 10, 9, 4, 9, 99, -1, 8];
             assert_eq!(
-                *evaluate_program(input, vec![value]).outputs.last().unwrap(),
+                *evaluate_program(input, vec![value]).outputs.back().unwrap(),
                 if value == 8 { 1 } else { 0 }
             );
         }
@@ -167,7 +157,7 @@ mod tests {
         for value in 5..10 {
             let input = vec![3, 9, 7, 9, 10, 9, 4, 9, 99, -1, 8];
             assert_eq!(
-                *evaluate_program(input, vec![value]).outputs.last().unwrap(),
+                *evaluate_program(input, vec![value]).outputs.back().unwrap(),
                 if value < 8 { 1 } else { 0 }
             );
         }
@@ -177,7 +167,7 @@ mod tests {
         for value in 5..10 {
             let input = vec![3, 3, 1108, -1, 8, 3, 4, 3, 99];
             assert_eq!(
-                *evaluate_program(input, vec![value]).outputs.last().unwrap(),
+                *evaluate_program(input, vec![value]).outputs.back().unwrap(),
                 if value == 8 { 1 } else { 0 }
             );
         }
@@ -187,7 +177,7 @@ mod tests {
         for value in 5..10 {
             let input = vec![3, 3, 1107, -1, 8, 3, 4, 3, 99];
             assert_eq!(
-                *evaluate_program(input, vec![value]).outputs.last().unwrap(),
+                *evaluate_program(input, vec![value]).outputs.back().unwrap(),
                 if value < 8 { 1 } else { 0 }
             );
         }
@@ -201,7 +191,7 @@ mod tests {
                 1, 20, 4, 20, 1105, 1, 46, 98, 99,
             ];
             assert_eq!(
-                *evaluate_program(input, vec![value]).outputs.last().unwrap(),
+                *evaluate_program(input, vec![value]).outputs.back().unwrap(),
                 if value < 8 {
                     999
                 } else if value == 8 {
@@ -218,7 +208,7 @@ mod tests {
         assert_eq!(
             *evaluate_program(input, Vec::<i32>::new())
                 .outputs
-                .last()
+                .back()
                 .unwrap(),
             27
         );
@@ -229,7 +219,7 @@ mod tests {
         assert_eq!(
             *evaluate_program(input, Vec::<i32>::new())
                 .outputs
-                .last()
+                .back()
                 .unwrap(),
             5
         );
@@ -277,7 +267,7 @@ mod tests {
             224, 674, 101, 1, 223, 223, 4, 223, 99, 226,
         ];
         assert_eq!(
-            *evaluate_program(input, vec![5]).outputs.last().unwrap(),
+            *evaluate_program(input, vec![5]).outputs.back().unwrap(),
             447803
         );
     }
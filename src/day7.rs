@@ -1,24 +1,33 @@
 use itertools::Itertools;
-use std::fs;
+use std::ops::Range;
 
-use crate::intcode::program::ProgramState;
+use crate::intcode::aot;
+use crate::intcode::loader::{Intcode, ProgramBuilder};
+use crate::intcode::pipeline::Topology;
+use crate::intcode::program::{ProgramState, Status};
+use crate::intcode::snapshot::Snapshot;
 
 pub fn day7(file_path: String) {
-    let contents = fs::read_to_string(file_path).expect("Should have been able to read the file");
+    let program: Intcode =
+        Intcode::from_file(file_path).expect("Should have been able to load the program");
 
-    let numbers: Vec<i32> = contents
-        .split(',')
-        .map(|s| s.trim())
-        .filter(|s| !s.is_empty())
-        .map(|s| s.parse().unwrap())
-        .collect();
-    println!("{:?}", find_optimal_inputs(&numbers));
+    println!("{:?}", find_optimal_inputs(&program.image));
+    println!("{:?}", find_optimal_feedback_inputs(&program.image));
+}
+
+fn find_optimal_inputs(program: &[i64]) -> i64 {
+    find_optimal_signal(program, 0..5)
 }
 
-fn find_optimal_inputs(program: &[i32]) -> i32 {
+fn find_optimal_feedback_inputs(program: &[i64]) -> i64 {
+    find_optimal_signal(program, 5..10)
+}
+
+fn find_optimal_signal(program: &[i64], phases: Range<i64>) -> i64 {
+    let phases: Vec<i64> = phases.collect();
     let mut max_output = 0;
-    for settings in (0..5).permutations(5) {
-        let output = calculate_signal(program, &settings);
+    for amplifiers in prime(program, &phases).into_iter().permutations(5) {
+        let output = calculate_signal(&amplifiers);
         if output > max_output {
             max_output = output;
         }
@@ -26,39 +35,53 @@ fn find_optimal_inputs(program: &[i32]) -> i32 {
     max_output
 }
 
-fn calculate_signal(program: &[i32], settings: &[i32]) -> i32 {
-    let mut previous_output = 0;
-    let mut amplifier = ProgramState {
-        program: program.to_vec(),
-        head: 0,
-        running: true,
-        inputs: vec![previous_output, settings[0]],
-        outputs: vec![],
-    };
-    amplifier.update();
-    for setting in settings.iter().take(5).skip(1) {
-        previous_output = amplifier.outputs.pop().unwrap();
-        amplifier = ProgramState {
-            program: program.to_vec(),
-            head: 0,
-            running: true,
-            inputs: vec![previous_output, *setting],
-            outputs: vec![],
-        };
-        amplifier.update();
-    }
-    amplifier.outputs.pop().unwrap()
+// Runs one amplifier per setting until it has read its phase and is waiting
+// for a signal. Every ordering of the amplifiers then resumes from these
+// snapshots instead of starting each machine from scratch.
+fn prime(program: &[i64], settings: &[i64]) -> Vec<Snapshot> {
+    settings
+        .iter()
+        .map(|setting| {
+            let mut amplifier = ProgramBuilder::new(program.to_vec())
+                .input(*setting)
+                .build();
+            assert_eq!(amplifier.update(), Ok(Status::NeedsInput));
+            amplifier.snapshot()
+        })
+        .collect()
+}
+
+// The amplifiers as a ring: with the first phase settings each passes one
+// signal along and halts, and E's output back to A goes unread; with the
+// feedback settings it loops until they halt. Either way the answer is E's
+// last output. Each amplifier runs through the build's translation of the
+// puzzle input, which falls back to the interpreter for any other program.
+fn calculate_signal(amplifiers: &[Snapshot]) -> i64 {
+    let mut machines: Vec<ProgramState> = amplifiers
+        .iter()
+        .map(|snapshot| snapshot.resume())
+        .collect();
+    machines[0].push_input(0);
+    let outputs = Topology::ring(machines.len())
+        .spawn_with(machines, |machine| aot::run_native(machine, aot::day7::run))
+        .join()
+        .expect("Should have been able to run the amplifiers");
+    *outputs
+        .last()
+        .expect("Amplifier E should have sent a signal")
 }
 
 #[cfg(test)]
 mod tests {
+    use crate::day7::prime;
+
     #[test]
     fn test_example_1() {
         let input = vec![
             3, 15, 3, 16, 1002, 16, 10, 16, 1, 16, 15, 15, 4, 15, 99, 0, 0,
         ];
         assert_eq!(
-            crate::day7::calculate_signal(&input, &vec![4, 3, 2, 1, 0]),
+            crate::day7::calculate_signal(&prime(&input, &[4, 3, 2, 1, 0])),
             43210
         );
         assert_eq!(crate::day7::find_optimal_inputs(&input), 43210);
@@ -70,7 +93,7 @@ mod tests {
             99, 0, 0,
         ];
         assert_eq!(
-            crate::day7::calculate_signal(&input, &vec![0, 1, 2, 3, 4]),
+            crate::day7::calculate_signal(&prime(&input, &[0, 1, 2, 3, 4])),
             54321
         );
         assert_eq!(crate::day7::find_optimal_inputs(&input), 54321);
@@ -82,9 +105,34 @@ mod tests {
             33, 31, 31, 1, 32, 31, 31, 4, 31, 99, 0, 0, 0,
         ];
         assert_eq!(
-            crate::day7::calculate_signal(&input, &vec![1, 0, 4, 3, 2]),
+            crate::day7::calculate_signal(&prime(&input, &[1, 0, 4, 3, 2])),
             65210
         );
         assert_eq!(crate::day7::find_optimal_inputs(&input), 65210);
     }
+    #[test]
+    fn test_feedback_example_1() {
+        let input = vec![
+            3, 26, 1001, 26, -4, 26, 3, 27, 1002, 27, 2, 27, 1, 27, 26, 27, 4, 27, 1001, 28, -1,
+            28, 1005, 28, 6, 99, 0, 0, 5,
+        ];
+        assert_eq!(
+            crate::day7::calculate_signal(&prime(&input, &[9, 8, 7, 6, 5])),
+            139629729
+        );
+        assert_eq!(crate::day7::find_optimal_feedback_inputs(&input), 139629729);
+    }
+    #[test]
+    fn test_feedback_example_2() {
+        let input = vec![
+            3, 52, 1001, 52, -5, 52, 3, 53, 1, 52, 56, 54, 1007, 54, 5, 55, 1005, 55, 26, 1001, 54,
+            -5, 54, 1105, 1, 12, 1, 53, 54, 53, 1008, 54, 0, 55, 1001, 55, 1, 55, 2, 53, 55, 53, 4,
+            53, 1001, 56, -1, 56, 1005, 56, 6, 99, 0, 0, 0, 0, 10,
+        ];
+        assert_eq!(
+            crate::day7::calculate_signal(&prime(&input, &[9, 7, 8, 5, 6])),
+            18216
+        );
+        assert_eq!(crate::day7::find_optimal_feedback_inputs(&input), 18216);
+    }
 }
@@ -1,2 +1,22 @@
+pub mod aot;
+pub mod ascii;
+pub mod asm;
+pub mod bench;
+pub mod cfg;
+pub mod debugger;
+pub mod disasm;
+pub mod engine;
+pub mod error;
+pub mod io;
+pub mod journal;
+pub mod loader;
+pub mod memory;
+pub mod network;
 pub mod opcode;
+pub mod pipeline;
+pub mod profile;
 pub mod program;
+pub mod snapshot;
+pub mod trace;
+pub mod watchdog;
+pub mod word;
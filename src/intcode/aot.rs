@@ -0,0 +1,482 @@
+use crate::intcode::error::VmError;
+use crate::intcode::loader::Intcode;
+use crate::intcode::opcode::{decode, Op, OpCode, ParamType};
+use crate::intcode::program::{ProgramState, Status, Step};
+
+pub mod codegen;
+
+pub use codegen::{translate_image, TranslateError};
+
+// Programs translated by `build.rs` from their inputs. A program that was
+// missing at build time gets a `run` that leaves everything to the
+// interpreter.
+pub mod tight_loop {
+    include!(concat!(env!("OUT_DIR"), "/tight_loop.rs"));
+}
+pub mod day7 {
+    include!(concat!(env!("OUT_DIR"), "/day7.rs"));
+}
+
+// The entry point of a translated program. It runs the machine from `head`
+// for as long as it can, and returns leaving `head` at the first
+// instruction it cannot handle: one that would halt the machine with an
+// error, is short of input, touches memory beyond the program's dense image
+// or writes over translated code. `Native` has the interpreter take those,
+// so errors and edge cases always behave exactly as they do in
+// `ProgramState`. It returns false, having done nothing, once memory no
+// longer holds the program it was translated from.
+pub type NativeFn = fn(&mut ProgramState) -> bool;
+
+// Copies memory into a plain vector for translated code, along with the
+// bound below which it may use addresses. None if the vector would not
+// cover the program image or a word marked in `code` has changed since.
+pub fn enter(state: &ProgramState, image: &[i64], code: &[u64]) -> Option<(Vec<i64>, usize)> {
+    let mut mem = Vec::new();
+    state.program.copy_dense(&mut mem);
+    let bound = state
+        .program
+        .limit()
+        .map_or(mem.len(), |limit| limit.min(mem.len()));
+    if bound < image.len() {
+        return None;
+    }
+    // Whole chunks compare much faster than word by word, and most of them
+    // are untouched.
+    let chunks = mem[..image.len()].chunks(64).zip(image.chunks(64));
+    for ((cells, words), bits) in chunks.zip(code) {
+        if cells != words
+            && cells
+                .iter()
+                .zip(words)
+                .enumerate()
+                .any(|(offset, (cell, word))| cell != word && bits >> offset & 1 == 1)
+        {
+            return None;
+        }
+    }
+    Some((mem, bound))
+}
+// Converts a computed address, or None if translated code should leave the
+// access to the interpreter.
+pub fn address(value: Option<i64>, bound: usize) -> Option<usize> {
+    value
+        .and_then(|value| usize::try_from(value).ok())
+        .filter(|&address| address < bound)
+}
+// Whether the translation baked in the word at `address`.
+pub fn is_code(code: &[u64], address: usize) -> bool {
+    code.get(address / 64)
+        .is_some_and(|bits| bits >> (address % 64) & 1 == 1)
+}
+// Runs the instruction at `head` for translated code that has no arm for
+// it, on the same memory and registers. Returns false with nothing changed
+// if the interpreter has to take it instead, for the same reasons as the
+// translated instructions, or because it halts.
+pub fn interpret(
+    state: &mut ProgramState,
+    mem: &mut [i64],
+    bound: usize,
+    code: &[u64],
+    head: &mut usize,
+    rb: &mut i64,
+    cycles: &mut u64,
+) -> bool {
+    let Some(opcode) = mem
+        .get(*head)
+        .and_then(|word| OpCode::parse(word, *head).ok())
+    else {
+        return false;
+    };
+    let op = opcode.op();
+    let mut next_head = *head + opcode.get_instruction_size();
+    if next_head > bound {
+        return false;
+    }
+    // Each parameter's value, and the address it names unless immediate.
+    let mut values = [0; 3];
+    let mut cells = [None; 3];
+    for (index, mode) in opcode.param_modes().iter().enumerate() {
+        let word = mem[*head + 1 + index];
+        let cell = match mode {
+            ParamType::Immediate => {
+                values[index] = word;
+                continue;
+            }
+            ParamType::Position => Some(word),
+            ParamType::Relative => rb.checked_add(word),
+        };
+        let Some(cell) = address(cell, bound) else {
+            return false;
+        };
+        values[index] = mem[cell];
+        cells[index] = Some(cell);
+    }
+    if op.writes_to_program() {
+        let target = cells[op.number_of_parameters() - 1];
+        let Some(target) = target.filter(|&target| !is_code(code, target)) else {
+            return false;
+        };
+        let value = match op {
+            Op::Add => values[0].checked_add(values[1]),
+            Op::Mult => values[0].checked_mul(values[1]),
+            Op::LessThan => Some(i64::from(values[0] < values[1])),
+            Op::Equals => Some(i64::from(values[0] == values[1])),
+            _ => state.next_input(),
+        };
+        let Some(value) = value else {
+            return false;
+        };
+        mem[target] = value;
+    } else {
+        match op {
+            Op::Read => state.emit_output(values[0]),
+            Op::JumpIfTrue | Op::JumpIfFalse => {
+                if (values[0] != 0) == (op == Op::JumpIfTrue) {
+                    let Some(target) = address(Some(values[1]), bound) else {
+                        return false;
+                    };
+                    next_head = target;
+                }
+            }
+            Op::AdjustRelativeBase => {
+                let Some(base) = rb.checked_add(values[0]) else {
+                    return false;
+                };
+                *rb = base;
+            }
+            _ => return false,
+        }
+    }
+    *head = next_head;
+    *cycles += 1;
+    true
+}
+// Writes translated code's memory and registers back.
+pub fn leave(
+    state: &mut ProgramState,
+    mem: &[i64],
+    head: usize,
+    relative_base: i64,
+    cycles: u64,
+    stopped: Option<usize>,
+) {
+    state.program.store_dense(mem);
+    state.relative_base = relative_base;
+    state.cycles = cycles;
+    match stopped {
+        Some(head) => state.head = head,
+        None => {
+            state.head = head;
+            state.running = false;
+        }
+    }
+}
+
+// Runs a machine through a translated program, with the interpreter taking
+// each instruction the translation leaves alone. Traced, journaled or watched
+// machines are interpreted throughout, and so is the rest of a run once the
+// program has been overwritten.
+#[derive(Debug)]
+pub struct Native {
+    program_state: ProgramState,
+    function: Option<NativeFn>,
+}
+impl Native {
+    pub fn new(program_state: ProgramState, function: NativeFn) -> Self {
+        Native {
+            program_state,
+            function: Some(function),
+        }
+    }
+    pub fn state(&self) -> &ProgramState {
+        &self.program_state
+    }
+    pub fn state_mut(&mut self) -> &mut ProgramState {
+        &mut self.program_state
+    }
+    pub fn into_inner(self) -> ProgramState {
+        self.program_state
+    }
+    // Whether the translation is still in use.
+    pub fn is_native(&self) -> bool {
+        self.function.is_some()
+    }
+    pub fn push_input(&mut self, value: i64) {
+        self.program_state.push_input(value);
+    }
+    pub fn extend_inputs<I: IntoIterator<Item = i64>>(&mut self, values: I) {
+        self.program_state.extend_inputs(values);
+    }
+    pub fn pop_output(&mut self) -> Option<i64> {
+        self.program_state.pop_output()
+    }
+    pub fn drain_outputs(&mut self) -> Vec<i64> {
+        self.program_state.drain_outputs()
+    }
+    // Runs until the program halts or needs input. Unlike
+    // `ProgramState::update` outputs do not stop it, so this never returns
+    // `Status::Output`.
+    pub fn update(&mut self) -> Result<Status, VmError> {
+        let recording = self.program_state.recording();
+        loop {
+            if let Some(function) = self.function.filter(|_| !recording) {
+                if !function(&mut self.program_state) {
+                    self.function = None;
+                }
+            }
+            match self.program_state.step()? {
+                None => return Ok(Status::Halted),
+                Some(Step {
+                    status: Some(Status::NeedsInput),
+                    ..
+                }) => return Ok(Status::NeedsInput),
+                Some(_) => {}
+            }
+        }
+    }
+    // Behaves exactly like `ProgramState::run`.
+    pub fn run(&mut self) -> Result<(), VmError> {
+        match self.update()? {
+            Status::NeedsInput => Err(VmError::InputUnderflow {
+                head: self.program_state.head,
+            }),
+            _ => Ok(()),
+        }
+    }
+}
+// `Native::run` for a machine given by value, as `Topology::spawn_with`
+// runs them.
+pub fn run_native(
+    program_state: ProgramState,
+    function: NativeFn,
+) -> (ProgramState, Result<(), VmError>) {
+    let mut native = Native::new(program_state, function);
+    let result = native.run();
+    (native.into_inner(), result)
+}
+
+// Prints the translation of a program, as `build.rs` would generate it.
+pub fn translate(file_path: String) {
+    let program: Intcode =
+        Intcode::from_file(file_path).expect("Should have been able to load the program");
+    match translate_image(&program.image) {
+        Ok(source) => print!("{}", source),
+        Err(error) => eprintln!("{}", error),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::intcode::aot::{
+        day7, interpret, tight_loop, translate_image, Native, TranslateError,
+    };
+    use crate::intcode::asm::{assemble, COUNTDOWN};
+    use crate::intcode::error::VmError;
+    use crate::intcode::loader::{Intcode, ProgramBuilder};
+    use crate::intcode::program::{ProgramState, Status};
+
+    // Runs both ways until the program halts, fails or needs input, and
+    // compares. Returns whether the translation was still in use at the end.
+    fn compare(image: &[i64], inputs: &[i64]) -> bool {
+        let mut interpreted = ProgramBuilder::new(image.to_vec())
+            .inputs(inputs.to_vec())
+            .build();
+        let status = loop {
+            match interpreted.update() {
+                Ok(Status::Output(_)) => {}
+                status => break status,
+            }
+        };
+        let program_state = ProgramBuilder::new(image.to_vec())
+            .inputs(inputs.to_vec())
+            .build();
+        let mut native = Native::new(program_state, day7::run);
+        assert_eq!(native.update(), status);
+        let is_native = native.is_native();
+        let native = native.into_inner();
+        assert_eq!(native.outputs, interpreted.outputs);
+        assert_eq!(native.cycles, interpreted.cycles);
+        assert_eq!(native.head, interpreted.head);
+        assert_eq!(native.program, interpreted.program);
+        is_native
+    }
+
+    #[test]
+    fn test_tight_loop_matches_build() {
+        let image = Intcode::from_file("inputs/tight-loop.txt").unwrap().image;
+        assert_eq!(
+            translate_image(&image).unwrap(),
+            include_str!(concat!(env!("OUT_DIR"), "/tight_loop.rs"))
+        );
+        // A shorter count keeps the interpreted run quick.
+        let mut image = image;
+        image[12] = 1000;
+        let mut native = Native::new(ProgramState::new(image.clone()), tight_loop::run);
+        native.run().unwrap();
+        assert!(native.is_native());
+        let mut interpreted = ProgramState::new(image);
+        interpreted.run().unwrap();
+        assert_eq!(native.state().program, interpreted.program);
+        assert_eq!(native.state().cycles, interpreted.cycles);
+    }
+    #[test]
+    fn test_matches_interpreter() {
+        let day7: Intcode = Intcode::from_file("inputs/day7-input.txt").unwrap();
+        for phase in 0..10 {
+            assert!(compare(&day7.image, &[phase, 17]));
+            assert!(compare(
+                &day7.image,
+                &[phase, 17, 3, 99, -4, 12, 0, 8, 1, 5, 6, 2]
+            ));
+        }
+        // The translation alone runs a whole amplifier.
+        let mut program_state = day7.builder().inputs([3, 17]).build();
+        assert!(day7::run(&mut program_state));
+        assert!(!program_state.running);
+        assert_eq!(program_state.outputs.len(), 1);
+        // The table only covers phases 0 to 9, and 99999 is not an opcode.
+        compare(&day7.image, &[10]);
+        compare(&day7.image, &[]);
+    }
+    #[test]
+    fn test_other_programs_are_interpreted() {
+        let day7: Intcode = Intcode::from_file("inputs/day7-input.txt").unwrap();
+        // Turning the first ADD into a MUL sends the controller elsewhere.
+        let mut image = day7.image.clone();
+        image[2] = 1002;
+        assert!(!compare(&image, &[3, 17]));
+        assert!(!compare(&[3, 0, 4, 0, 99], &[5]));
+    }
+    #[test]
+    fn test_pauses_for_input() {
+        let day7: Intcode = Intcode::from_file("inputs/day7-input.txt").unwrap();
+        let mut native = Native::new(day7.builder().input(7).build(), day7::run);
+        assert_eq!(native.update(), Ok(Status::NeedsInput));
+        let mut signal = 0;
+        while native.state().running {
+            native.push_input(signal);
+            native.update().unwrap();
+            signal = native.pop_output().unwrap();
+        }
+        let mut interpreted = day7.builder().input(7).build();
+        let mut expected = 0;
+        while interpreted.running {
+            interpreted.push_input(expected);
+            while let Ok(Status::Output(value)) = interpreted.update() {
+                expected = value;
+            }
+        }
+        assert_eq!(signal, expected);
+        assert_eq!(
+            Native::new(day7.builder().build(), day7::run).run(),
+            Err(VmError::InputUnderflow { head: 0 })
+        );
+    }
+    #[test]
+    fn test_translation() {
+        let source = translate_image(&assemble(COUNTDOWN).unwrap()).unwrap();
+        assert!(source.contains("// Entry points: 0.\n"));
+        assert!(source.contains("static CODE: [u64; 1] = [\n    0xfff,\n];\n"));
+        assert!(source.contains(
+            "                    // 4: ADD [12], #-1, [12]\n                    let Some(v) = mem[12].checked_add(-1) else { break 'run Some(4) };\n                    mem[12] = v;\n"
+        ));
+        assert!(source.contains("                    if mem[12] != 0 {\n                        cycles += 1;\n                        head = 2;\n"));
+        assert!(source.contains("                _ => break 'fallback,\n"));
+        // A write through the relative base could land on the code.
+        let source =
+            translate_image(&assemble("ARB rb+2\nADD #1, #2, rb+3\nHLT").unwrap()).unwrap();
+        assert!(source.contains(
+            "let Some(p0) = aot::address(rb.checked_add(2), bound) else { break 'run Some(0) };"
+        ));
+        assert!(source.contains(
+            "let Some(p2) = aot::address(rb.checked_add(3), bound) else { break 'run Some(2) };\n                    if aot::is_code(&CODE, p2) { break 'run Some(2) }\n"
+        ));
+    }
+    #[test]
+    fn test_finds_indirect_targets() {
+        // A jump table read through a patched operand, and a call whose
+        // return address is pushed as a constant.
+        let image = assemble(
+            "
+                        IN [i]
+                        ADD [i], #table, [jump+2]
+                jump:   JT #1, [0]
+                one:    OUT #1
+                        HLT
+                two:    ADD #ret, #0, rb
+                        JT #1, #double
+                ret:    HLT
+                double: OUT #2
+                        JT #1, rb
+                i:      data 0
+                table:  data one, two",
+        )
+        .unwrap();
+        let source = translate_image(&image).unwrap();
+        // 2 is the operand of `OUT #2`, taken for data before the handlers
+        // were found; an extra arm for it costs nothing.
+        assert!(source.contains("// Entry points: 0, 2, 9, 12, 19, 20.\n"));
+        for arm in [9, 12, 19, 20] {
+            assert!(source.contains(&format!("                {} => {{\n", arm)));
+        }
+    }
+    #[test]
+    fn test_interpret() {
+        // ADD [5], #3, [5]; HLT; data 7
+        let mut state = ProgramState::new(vec![1001, 5, 3, 5, 99, 7]);
+        let mut mem = vec![1001, 5, 3, 5, 99, 7];
+        let (mut head, mut rb, mut cycles) = (0, 0, 0);
+        assert!(interpret(
+            &mut state,
+            &mut mem,
+            6,
+            &[],
+            &mut head,
+            &mut rb,
+            &mut cycles
+        ));
+        assert_eq!((mem[5], head, cycles), (10, 4, 1));
+        // Halting is the interpreter's business.
+        assert!(!interpret(
+            &mut state,
+            &mut mem,
+            6,
+            &[],
+            &mut head,
+            &mut rb,
+            &mut cycles
+        ));
+        // So are writes over translated code and accesses past the bound.
+        head = 0;
+        assert!(!interpret(
+            &mut state,
+            &mut mem,
+            6,
+            &[1 << 5],
+            &mut head,
+            &mut rb,
+            &mut cycles
+        ));
+        assert!(!interpret(
+            &mut state,
+            &mut mem,
+            5,
+            &[],
+            &mut head,
+            &mut rb,
+            &mut cycles
+        ));
+        assert_eq!((mem[5], head, cycles), (10, 0, 1));
+    }
+    #[test]
+    fn test_rejects_self_modifying_code() {
+        let day5: Intcode = Intcode::from_file("inputs/day5-input.txt").unwrap();
+        assert_eq!(
+            translate_image(&day5.image),
+            Err(TranslateError::SelfModifying {
+                address: 2,
+                target: 6
+            })
+        );
+    }
+}
@@ -0,0 +1,366 @@
+use crate::intcode::error::VmError;
+use crate::intcode::loader::Intcode;
+use crate::intcode::program::{ProgramState, Status};
+use crate::intcode::word::Word;
+use std::fmt;
+use std::fs;
+use std::io::{self, BufRead, Write};
+
+const HELP: &str = "\
+Lines are sent to the program as ASCII, each followed by a newline.
+:history          list the lines sent so far
+!<n>              send line n of the history again
+:save <path>      write the history to path, one line per input
+:replay <path>    send each line of path in turn
+:quit             end the session";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AsciiError {
+    NotAscii(char),
+}
+impl fmt::Display for AsciiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AsciiError::NotAscii(character) => write!(f, "{:?} is not ASCII", character),
+        }
+    }
+}
+impl std::error::Error for AsciiError {}
+
+// Output as an ASCII program means it: runs of characters as text, and
+// anything outside 0..=127 as a value in its own right, usually the answer.
+#[derive(Debug, Clone, PartialEq)]
+pub enum AsciiOutput<W: Word = i64> {
+    Text(String),
+    Value(W),
+}
+
+// The codes for `line` followed by a newline.
+pub fn encode<W: Word>(line: &str) -> Result<Vec<W>, AsciiError> {
+    line.chars()
+        .chain(Some('\n'))
+        .map(|character| match character.is_ascii() {
+            true => Ok(W::from_i64(character as i64)
+                .expect("Should have been able to fit an ASCII code in a word")),
+            false => Err(AsciiError::NotAscii(character)),
+        })
+        .collect()
+}
+
+pub fn decode<W: Word>(values: &[W]) -> Vec<AsciiOutput<W>> {
+    let mut decoded = Vec::new();
+    for value in values {
+        let character = value
+            .to_i64()
+            .and_then(|code| u8::try_from(code).ok())
+            .filter(u8::is_ascii)
+            .map(char::from);
+        match (character, decoded.last_mut()) {
+            (Some(character), Some(AsciiOutput::Text(text))) => text.push(character),
+            (Some(character), _) => decoded.push(AsciiOutput::Text(character.to_string())),
+            (None, _) => decoded.push(AsciiOutput::Value(value.clone())),
+        }
+    }
+    decoded
+}
+
+// A program that reads and writes lines of ASCII. It uses the machine's own
+// queues, so the machine should not have `io` set.
+#[derive(Debug)]
+pub struct AsciiMachine<W: Word = i64> {
+    pub program_state: ProgramState<W>,
+}
+impl<W: Word> AsciiMachine<W> {
+    pub fn new(program_state: ProgramState<W>) -> Self {
+        AsciiMachine { program_state }
+    }
+    pub fn send_line(&mut self, line: &str) -> Result<(), AsciiError> {
+        let codes = encode(line)?;
+        self.program_state.extend_inputs(codes);
+        Ok(())
+    }
+    // Runs until the program halts or wants more input than it has been sent.
+    pub fn run(&mut self) -> Result<Status<W>, VmError> {
+        loop {
+            match self.program_state.update()? {
+                Status::Output(_) => continue,
+                status => return Ok(status),
+            }
+        }
+    }
+    // Everything the program has written since the last call.
+    pub fn take_output(&mut self) -> Vec<AsciiOutput<W>> {
+        decode(&self.program_state.drain_outputs())
+    }
+}
+
+// Drives an ASCII program from a terminal, remembering every line sent so a
+// session can be saved and replayed.
+pub struct Console<W: Word = i64> {
+    pub machine: AsciiMachine<W>,
+    pub history: Vec<String>,
+    // Whether the last thing written ended a line, so values start on their
+    // own line.
+    at_line_start: bool,
+}
+impl<W: Word> Console<W> {
+    pub fn new(program_state: ProgramState<W>) -> Self {
+        Console {
+            machine: AsciiMachine::new(program_state),
+            history: Vec::new(),
+            at_line_start: true,
+        }
+    }
+    // Runs the program up to its first request for input. Returns false if it
+    // stopped instead.
+    pub fn start<O: Write>(&mut self, out: &mut O) -> io::Result<bool> {
+        self.resume(out)
+    }
+    // Reads lines until `:quit`, the program stops or `input` ends.
+    pub fn repl<I: BufRead, O: Write>(&mut self, input: I, mut out: O) -> io::Result<()> {
+        for line in input.lines() {
+            if !self.execute(&line?, &mut out)? {
+                return Ok(());
+            }
+        }
+        writeln!(out)
+    }
+    // Handles one line typed at the prompt. Returns false when the session
+    // should end.
+    pub fn execute<O: Write>(&mut self, line: &str, out: &mut O) -> io::Result<bool> {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        match words.as_slice() {
+            [":history"] => {
+                for (index, line) in self.history.iter().enumerate() {
+                    writeln!(out, "{:>4}  {}", index + 1, line)?;
+                }
+            }
+            [":save", path] => {
+                let mut script = self.history.join("\n");
+                script.push('\n');
+                match fs::write(path, script) {
+                    Ok(()) => writeln!(out, "saved {} line(s) to {}", self.history.len(), path)?,
+                    Err(error) => writeln!(out, "could not save {}: {}", path, error)?,
+                }
+            }
+            [":replay", path] => match fs::read_to_string(path) {
+                Ok(script) => return self.replay(&script, out),
+                Err(error) => writeln!(out, "could not read {}: {}", path, error)?,
+            },
+            [":help"] => writeln!(out, "{}", HELP)?,
+            [":quit"] => return Ok(false),
+            [command] if command.starts_with('!') => {
+                let entry = command[1..]
+                    .parse::<usize>()
+                    .ok()
+                    .and_then(|number| self.history.get(number.checked_sub(1)?));
+                match entry.cloned() {
+                    Some(line) => {
+                        writeln!(out, "{}", line)?;
+                        return self.send(&line, out);
+                    }
+                    None => writeln!(out, "no line {} in the history", &command[1..])?,
+                }
+            }
+            [command, ..] if command.starts_with(':') => {
+                writeln!(out, "unknown command {:?}; try :help", line.trim())?
+            }
+            _ => return self.send(line, out),
+        }
+        write!(out, "> ")?;
+        out.flush()?;
+        Ok(true)
+    }
+    // Sends each line of `script` as though it had been typed, stopping early
+    // if the program does.
+    pub fn replay<O: Write>(&mut self, script: &str, out: &mut O) -> io::Result<bool> {
+        for line in script.lines() {
+            writeln!(out, "{}", line)?;
+            if !self.send(line, out)? {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+    fn send<O: Write>(&mut self, line: &str, out: &mut O) -> io::Result<bool> {
+        if let Err(error) = self.machine.send_line(line) {
+            writeln!(out, "input must be ASCII: {}", error)?;
+            write!(out, "> ")?;
+            out.flush()?;
+            return Ok(true);
+        }
+        self.history.push(line.to_string());
+        self.at_line_start = true;
+        self.resume(out)
+    }
+    // Runs the program and shows what it wrote, then prompts for the next
+    // line if it wants one.
+    fn resume<O: Write>(&mut self, out: &mut O) -> io::Result<bool> {
+        let status = self.machine.run();
+        for output in self.machine.take_output() {
+            match output {
+                AsciiOutput::Text(text) => {
+                    write!(out, "{}", text)?;
+                    self.at_line_start = text.ends_with('\n');
+                }
+                AsciiOutput::Value(value) => {
+                    if !self.at_line_start {
+                        writeln!(out)?;
+                    }
+                    writeln!(out, "{}", value)?;
+                    self.at_line_start = true;
+                }
+            }
+        }
+        if !self.at_line_start {
+            writeln!(out)?;
+        }
+        match status {
+            Ok(Status::NeedsInput) => {
+                write!(out, "> ")?;
+                out.flush()?;
+                Ok(true)
+            }
+            Ok(_) => {
+                writeln!(
+                    out,
+                    "halted after {} cycles",
+                    self.machine.program_state.cycles
+                )?;
+                Ok(false)
+            }
+            Err(error) => {
+                writeln!(out, "error: {}", error)?;
+                Ok(false)
+            }
+        }
+    }
+}
+
+// Replays each script in turn, then hands the program over to the terminal.
+pub fn ascii(file_path: String, scripts: &[String]) {
+    let program: Intcode =
+        Intcode::from_file(file_path).expect("Should have been able to load the program");
+    let mut console = Console::new(program.builder().build());
+    let mut out = io::stdout();
+    let mut running = console
+        .start(&mut out)
+        .expect("Should have been able to use the terminal");
+    for script in scripts {
+        if !running {
+            break;
+        }
+        let script = fs::read_to_string(script).expect("Should have been able to read the script");
+        running = console
+            .replay(&script, &mut out)
+            .expect("Should have been able to use the terminal");
+    }
+    if running {
+        let stdin = io::stdin();
+        console
+            .repl(stdin.lock(), out)
+            .expect("Should have been able to use the terminal");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::intcode::ascii::{decode, encode, AsciiError, AsciiMachine, AsciiOutput, Console};
+    use crate::intcode::asm::assemble;
+    use crate::intcode::program::{ProgramState, Status};
+
+    // Greets, echoes two lines back and then reports a value too big to be
+    // a character.
+    const ECHO: &str = "
+                OUT #72
+                OUT #105
+                OUT #10
+        loop:   IN [c]
+                OUT [c]
+                EQ [c], #10, [t]
+                JF [t], #loop
+                ADD [n], #1, [n]
+                EQ [n], #2, [t]
+                JF [t], #loop
+                OUT #1000
+                HLT
+        c:      data 0
+        t:      data 0
+        n:      data 0";
+
+    fn echo() -> ProgramState {
+        ProgramState::new(assemble(ECHO).unwrap())
+    }
+    fn session(console: &mut Console, commands: &str) -> String {
+        let mut out = Vec::new();
+        if console.start(&mut out).unwrap() {
+            console.repl(commands.as_bytes(), &mut out).unwrap();
+        }
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn test_encode_and_decode() {
+        assert_eq!(encode::<i64>("Hi"), Ok(vec![72, 105, 10]));
+        assert_eq!(encode::<i64>("né"), Err(AsciiError::NotAscii('é')));
+        assert_eq!(
+            decode(&[72, 105, 10, 128, -1, 33]),
+            vec![
+                AsciiOutput::Text("Hi\n".to_string()),
+                AsciiOutput::Value(128),
+                AsciiOutput::Value(-1),
+                AsciiOutput::Text("!".to_string()),
+            ]
+        );
+    }
+    #[test]
+    fn test_machine() {
+        let mut machine = AsciiMachine::new(echo());
+        assert_eq!(machine.run(), Ok(Status::NeedsInput));
+        assert_eq!(
+            machine.take_output(),
+            vec![AsciiOutput::Text("Hi\n".to_string())]
+        );
+        machine.send_line("ab").unwrap();
+        assert_eq!(machine.run(), Ok(Status::NeedsInput));
+        assert_eq!(
+            machine.take_output(),
+            vec![AsciiOutput::Text("ab\n".to_string())]
+        );
+        machine.send_line("c").unwrap();
+        assert_eq!(machine.run(), Ok(Status::Halted));
+        assert_eq!(
+            machine.take_output(),
+            vec![
+                AsciiOutput::Text("c\n".to_string()),
+                AsciiOutput::Value(1000)
+            ]
+        );
+    }
+    #[test]
+    fn test_console() {
+        let mut console = Console::new(echo());
+        let transcript = session(&mut console, "ab\n:history\nné\n:frob\n!3\n!1\n");
+        assert!(transcript.starts_with("Hi\n> ab\n> "));
+        assert!(transcript.contains("   1  ab\n> "));
+        assert!(transcript.contains("input must be ASCII: 'é' is not ASCII\n> "));
+        assert!(transcript.contains("unknown command \":frob\"; try :help\n> "));
+        assert!(transcript.contains("no line 3 in the history\n> "));
+        assert!(transcript.ends_with("ab\nab\n1000\nhalted after 35 cycles\n"));
+        assert_eq!(console.history, vec!["ab", "ab"]);
+    }
+    #[test]
+    fn test_save_and_replay() {
+        let path = std::env::temp_dir().join(format!("ascii-{}.txt", std::process::id()));
+        let path = path.to_str().unwrap();
+        let mut console = Console::new(echo());
+        session(&mut console, &format!("first\n:save {}\n:quit\n", path));
+        assert_eq!(std::fs::read_to_string(path).unwrap(), "first\n");
+
+        let mut console = Console::new(echo());
+        let transcript = session(&mut console, &format!(":replay {}\nsecond\n", path));
+        std::fs::remove_file(path).unwrap();
+        assert!(transcript.starts_with("Hi\n> first\nfirst\n> second\n1000\n"));
+        assert_eq!(console.history, vec!["first", "second"]);
+    }
+}
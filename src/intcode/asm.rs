@@ -0,0 +1,506 @@
+use crate::intcode::opcode::{Op, OpCode, ParamType};
+use crate::intcode::word::Word;
+use std::collections::HashMap;
+use std::fmt;
+
+// A small assembly language for Intcode, matching the disassembler's output:
+//
+//     start:  IN [n]              ; position operand
+//     loop:   OUT [n]
+//             ADD [n], #-1, [n]   ; immediate operand
+//             JT [n], #loop       ; labels stand for their address
+//             HLT
+//     n:      data 0
+//
+// Relative operands are written `rb+3`, `rb-1` or `rb`. A numeric label such
+// as `12:` asserts the address of the next word instead of defining a name.
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AsmError {
+    pub line: usize,
+    pub column: usize,
+    pub message: String,
+}
+impl fmt::Display for AsmError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}: {}", self.line, self.column, self.message)
+    }
+}
+impl std::error::Error for AsmError {}
+
+enum Value<W: Word> {
+    Literal(W),
+    Label {
+        name: String,
+        offset: i64,
+        line: usize,
+        column: usize,
+    },
+}
+struct Operand<W: Word> {
+    mode: ParamType,
+    value: Value<W>,
+}
+enum Item<W: Word> {
+    Instruction { op: Op, operands: Vec<Operand<W>> },
+    Data(Vec<Value<W>>),
+}
+
+struct Cursor<'a> {
+    text: &'a str,
+    position: usize,
+    line: usize,
+}
+impl<'a> Cursor<'a> {
+    fn error_at(&self, position: usize, message: String) -> AsmError {
+        AsmError {
+            line: self.line,
+            column: self.text[..position].chars().count() + 1,
+            message,
+        }
+    }
+    fn error(&self, message: String) -> AsmError {
+        self.error_at(self.position, message)
+    }
+    fn column(&self) -> usize {
+        self.text[..self.position].chars().count() + 1
+    }
+    fn skip_whitespace(&mut self) {
+        let rest = &self.text[self.position..];
+        self.position += rest.len() - rest.trim_start().len();
+    }
+    // Whitespace is skipped before every token.
+    fn peek(&mut self) -> Option<char> {
+        self.skip_whitespace();
+        match self.text[self.position..].chars().next() {
+            Some(';') | None => None,
+            other => other,
+        }
+    }
+    fn eat(&mut self, expected: char) -> bool {
+        if self.peek() == Some(expected) {
+            self.position += expected.len_utf8();
+            true
+        } else {
+            false
+        }
+    }
+    fn expect(&mut self, expected: char) -> Result<(), AsmError> {
+        if self.eat(expected) {
+            Ok(())
+        } else {
+            Err(self.error(format!("expected '{}'", expected)))
+        }
+    }
+    fn take_while<F: Fn(char) -> bool>(&mut self, predicate: F) -> &'a str {
+        let start = self.position;
+        let rest = &self.text[start..];
+        let length = rest.find(|c: char| !predicate(c)).unwrap_or(rest.len());
+        self.position += length;
+        &self.text[start..start + length]
+    }
+    fn identifier(&mut self) -> Option<&'a str> {
+        match self.peek() {
+            Some(c) if c.is_ascii_alphabetic() || c == '_' => {
+                Some(self.take_while(|c| c.is_ascii_alphanumeric() || c == '_'))
+            }
+            _ => None,
+        }
+    }
+    // An optionally negative run of digits.
+    fn number(&mut self) -> Option<&'a str> {
+        self.skip_whitespace();
+        let start = self.position;
+        self.eat('-');
+        if self.take_while(|c| c.is_ascii_digit()).is_empty() {
+            self.position = start;
+            return None;
+        }
+        Some(&self.text[start..self.position])
+    }
+    fn literal<W: Word>(&mut self) -> Result<Option<W>, AsmError> {
+        self.skip_whitespace();
+        let start = self.position;
+        match self.number() {
+            Some(text) => match text.parse() {
+                Ok(value) => Ok(Some(value)),
+                Err(_) => Err(self.error_at(start, format!("value {} is out of range", text))),
+            },
+            None => Ok(None),
+        }
+    }
+    // A literal, or a label optionally followed by `+n` or `-n`.
+    fn value<W: Word>(&mut self) -> Result<Value<W>, AsmError> {
+        if let Some(value) = self.literal()? {
+            return Ok(Value::Literal(value));
+        }
+        let column = self.column();
+        let Some(name) = self.identifier() else {
+            return Err(self.error("expected a number or a label".to_string()));
+        };
+        let mut offset = 0;
+        if self.peek() == Some('+') || self.peek() == Some('-') {
+            let negative = self.peek() == Some('-');
+            self.position += 1;
+            self.skip_whitespace();
+            let start = self.position;
+            offset = self
+                .take_while(|c| c.is_ascii_digit())
+                .parse::<i64>()
+                .map_err(|_| self.error_at(start, "expected an offset".to_string()))?;
+            if negative {
+                offset = -offset;
+            }
+        }
+        Ok(Value::Label {
+            name: name.to_string(),
+            offset,
+            line: self.line,
+            column,
+        })
+    }
+    fn operand<W: Word>(&mut self) -> Result<Operand<W>, AsmError> {
+        if self.eat('#') {
+            return Ok(Operand {
+                mode: ParamType::Immediate,
+                value: self.value()?,
+            });
+        }
+        if self.eat('[') {
+            let value = self.value()?;
+            self.expect(']')?;
+            return Ok(Operand {
+                mode: ParamType::Position,
+                value,
+            });
+        }
+        let start = self.position;
+        match self.identifier() {
+            Some(name) if name.eq_ignore_ascii_case("rb") => {
+                let value = if self.peek() == Some('+') {
+                    self.position += 1;
+                    self.skip_whitespace();
+                    self.literal()?
+                } else if self.peek() == Some('-') {
+                    self.literal()?
+                } else {
+                    Some(W::default())
+                };
+                match value {
+                    Some(value) => Ok(Operand {
+                        mode: ParamType::Relative,
+                        value: Value::Literal(value),
+                    }),
+                    None => Err(self.error("expected a relative offset".to_string())),
+                }
+            }
+            _ => Err(self.error_at(
+                start,
+                "expected an operand like [12], #5 or rb+3".to_string(),
+            )),
+        }
+    }
+}
+
+struct Assembler<W: Word> {
+    items: Vec<Item<W>>,
+    labels: HashMap<String, usize>,
+    address: usize,
+}
+impl<W: Word> Assembler<W> {
+    fn parse_line(&mut self, text: &str, line: usize) -> Result<(), AsmError> {
+        let mut cursor = Cursor {
+            text,
+            position: 0,
+            line,
+        };
+        loop {
+            cursor.skip_whitespace();
+            let start = cursor.position;
+            if let Some(number) = cursor.number() {
+                cursor.expect(':')?;
+                let expected: usize = number.parse().map_err(|_| {
+                    cursor.error_at(start, format!("{} is not a valid address", number))
+                })?;
+                if expected != self.address {
+                    return Err(cursor.error_at(
+                        start,
+                        format!(
+                            "address {} does not match assembled address {}",
+                            expected, self.address
+                        ),
+                    ));
+                }
+                continue;
+            }
+            let Some(word) = cursor.identifier() else {
+                if cursor.peek().is_some() {
+                    return Err(cursor.error("expected a label or an instruction".to_string()));
+                }
+                return Ok(());
+            };
+            if cursor.eat(':') {
+                if self.labels.insert(word.to_string(), self.address).is_some() {
+                    return Err(
+                        cursor.error_at(start, format!("label {} is already defined", word))
+                    );
+                }
+                continue;
+            }
+            let item = if word.eq_ignore_ascii_case("data") {
+                let mut values = vec![cursor.value()?];
+                while cursor.eat(',') {
+                    values.push(cursor.value()?);
+                }
+                Item::Data(values)
+            } else {
+                let op = Op::from_mnemonic(word)
+                    .ok_or_else(|| cursor.error_at(start, format!("unknown mnemonic {}", word)))?;
+                let mut operands = Vec::new();
+                if op.number_of_parameters() > 0 {
+                    operands.push(cursor.operand()?);
+                    while cursor.eat(',') {
+                        operands.push(cursor.operand()?);
+                    }
+                }
+                if operands.len() != op.number_of_parameters() {
+                    return Err(cursor.error_at(
+                        start,
+                        format!(
+                            "{} takes {} operands but was given {}",
+                            op.mnemonic(),
+                            op.number_of_parameters(),
+                            operands.len()
+                        ),
+                    ));
+                }
+                if op.writes_to_program()
+                    && operands.last().map(|operand| operand.mode) == Some(ParamType::Immediate)
+                {
+                    return Err(cursor.error_at(
+                        start,
+                        format!("{} cannot write to an immediate operand", op.mnemonic()),
+                    ));
+                }
+                Item::Instruction { op, operands }
+            };
+            if cursor.peek().is_some() {
+                return Err(cursor.error("unexpected text after statement".to_string()));
+            }
+            self.address += match &item {
+                Item::Instruction { operands, .. } => 1 + operands.len(),
+                Item::Data(values) => values.len(),
+            };
+            self.items.push(item);
+            return Ok(());
+        }
+    }
+    fn resolve(&self, value: Value<W>) -> Result<W, AsmError> {
+        match value {
+            Value::Literal(value) => Ok(value),
+            Value::Label {
+                name,
+                offset,
+                line,
+                column,
+            } => match self.labels.get(&name) {
+                Some(&address) => i64::try_from(address)
+                    .ok()
+                    .and_then(|address| address.checked_add(offset))
+                    .and_then(W::from_i64)
+                    .ok_or_else(|| AsmError {
+                        line,
+                        column,
+                        message: format!("{}{:+} does not fit in a word", name, offset),
+                    }),
+                None => Err(AsmError {
+                    line,
+                    column,
+                    message: format!("undefined label {}", name),
+                }),
+            },
+        }
+    }
+}
+
+pub fn assemble<W: Word>(source: &str) -> Result<Vec<W>, AsmError> {
+    let mut assembler = Assembler {
+        items: Vec::new(),
+        labels: HashMap::new(),
+        address: 0,
+    };
+    for (index, text) in source.lines().enumerate() {
+        assembler.parse_line(text, index + 1)?;
+    }
+    let items = std::mem::take(&mut assembler.items);
+    let mut image = Vec::with_capacity(assembler.address);
+    for item in items {
+        match item {
+            Item::Instruction { op, operands } => {
+                let modes: Vec<ParamType> = operands.iter().map(|operand| operand.mode).collect();
+                let code = OpCode::new(op, &modes).code();
+                image.push(
+                    W::from_i64(code).expect("Should have been able to fit an opcode in a word"),
+                );
+                for operand in operands {
+                    image.push(assembler.resolve(operand.value)?);
+                }
+            }
+            Item::Data(values) => {
+                for value in values {
+                    image.push(assembler.resolve(value)?);
+                }
+            }
+        }
+    }
+    Ok(image)
+}
+
+// Reads a count and outputs it, then each smaller number down to 1. Shared
+// by the tests of everything that runs or inspects programs.
+#[cfg(test)]
+pub(crate) const COUNTDOWN: &str = "
+                IN [n]
+        loop:   OUT [n]
+                ADD [n], #-1, [n]
+                JT [n], #loop
+                HLT
+        n:      data 0";
+
+#[cfg(test)]
+mod tests {
+    use crate::intcode::asm::{assemble, AsmError, COUNTDOWN};
+    use crate::intcode::disasm::disassemble;
+    use crate::intcode::loader::{Intcode, ProgramBuilder};
+
+    fn run(source: &str, inputs: Vec<i64>) -> Vec<i64> {
+        let mut program_state = ProgramBuilder::new(assemble(source).unwrap())
+            .inputs(inputs)
+            .build();
+        program_state.run().unwrap();
+        program_state.drain_outputs()
+    }
+    #[test]
+    fn test_assemble() {
+        let image: Vec<i64> = assemble(
+            "
+            ; the day 5 example
+                    MUL [4], #3, [4]
+                    data 33
+            ",
+        )
+        .unwrap();
+        assert_eq!(image, vec![1002, 4, 3, 4, 33]);
+    }
+    #[test]
+    fn test_labels() {
+        let image: Vec<i64> = assemble(
+            "start:  IN [n]
+             loop:   JF [n], #end
+                     OUT [n]
+                     add [n], #-1, [n]
+                     JT #1, #loop
+             end:    HLT
+             n:      data 0, n+1, n-1",
+        )
+        .unwrap();
+        assert_eq!(
+            image,
+            vec![3, 15, 1006, 15, 14, 4, 15, 1001, 15, -1, 15, 1105, 1, 2, 99, 0, 16, 14]
+        );
+    }
+    #[test]
+    fn test_relative_operands() {
+        let image: Vec<i64> = assemble("ARB #-3\nADD rb-1, #7, rb+3\nOUT rb\nHLT").unwrap();
+        assert_eq!(image, vec![109, -3, 21201, -1, 7, 3, 204, 0, 99]);
+    }
+    fn round_trip(image: &[i64]) -> Result<Vec<i64>, AsmError> {
+        let listing: Vec<String> = disassemble(image)
+            .iter()
+            .map(|line| line.to_string())
+            .collect();
+        assemble(&listing.join("\n"))
+    }
+    #[test]
+    fn test_round_trip() {
+        let program: Intcode = Intcode::from_file("inputs/day5-input.txt").unwrap();
+        assert_eq!(round_trip(&program.image), Ok(program.image));
+    }
+    #[test]
+    fn test_round_trip_every_word() {
+        // Every word the VM accepts as an instruction, canonical or not,
+        // followed by operands that fit any mode.
+        for word in 0..100_000 {
+            let image = vec![word, 1, 2, 3, 99];
+            assert_eq!(round_trip(&image), Ok(image));
+        }
+    }
+    #[test]
+    fn test_errors() {
+        let error = |line, column, message: &str| {
+            Err(AsmError {
+                line,
+                column,
+                message: message.to_string(),
+            })
+        };
+        assert_eq!(
+            assemble::<i64>("HLT\n  FOO [1]"),
+            error(2, 3, "unknown mnemonic FOO")
+        );
+        assert_eq!(
+            assemble::<i64>("ADD [1], #2, #3"),
+            error(1, 1, "ADD cannot write to an immediate operand")
+        );
+        assert_eq!(
+            assemble::<i64>("OUT [1], [2]"),
+            error(1, 1, "OUT takes 1 operands but was given 2")
+        );
+        assert_eq!(
+            assemble::<i64>("JT #1, #nowhere"),
+            error(1, 9, "undefined label nowhere")
+        );
+        assert_eq!(assemble::<i64>("OUT [1\nHLT"), error(1, 7, "expected ']'"));
+        assert_eq!(
+            assemble::<i64>("OUT 1"),
+            error(1, 5, "expected an operand like [12], #5 or rb+3")
+        );
+        assert_eq!(
+            assemble::<i64>("a: HLT\na: HLT"),
+            error(2, 1, "label a is already defined")
+        );
+        assert_eq!(
+            assemble::<i64>("0: HLT\n2: HLT"),
+            error(2, 1, "address 2 does not match assembled address 1")
+        );
+        assert_eq!(
+            assemble::<i64>("HLT HLT"),
+            error(1, 5, "unexpected text after statement")
+        );
+        // The label's address fits an i32 but the sum does not.
+        assert_eq!(
+            assemble::<i32>("n: data n+2147483648").unwrap_err(),
+            error(1, 9, "n+2147483648 does not fit in a word").unwrap_err()
+        );
+    }
+    #[test]
+    fn test_countdown() {
+        assert_eq!(run(COUNTDOWN, vec![3]), vec![3, 2, 1]);
+    }
+    #[test]
+    fn test_relative_base_call() {
+        // Calls a doubling subroutine that keeps its return address and
+        // argument on a stack addressed through the relative base.
+        let source = "
+                    ARB #stack
+                    IN rb+1
+                    ADD #back, #0, rb
+                    JT #1, #double
+            back:   OUT rb+1
+                    HLT
+            double: MUL rb+1, #2, rb+1
+                    JT #1, rb
+            stack:  data 0, 0";
+        assert_eq!(run(source, vec![21]), vec![42]);
+    }
+}
@@ -0,0 +1,197 @@
+use crate::intcode::aot::{self, NativeFn};
+use crate::intcode::engine::Engine;
+use crate::intcode::error::VmError;
+use crate::intcode::loader::Intcode;
+use crate::intcode::program::ProgramState;
+use itertools::Itertools;
+use std::time::{Duration, Instant};
+
+// Each workload is timed this many times on each path and the fastest run
+// is reported, which filters out most scheduling noise.
+const REPEATS: usize = 3;
+
+#[derive(Debug, Clone, Copy)]
+enum Path {
+    // `ProgramState::run`, decoding every instruction as it is reached.
+    Step,
+    // `Engine::run`, with decoded instructions cached.
+    Engine,
+    // `Native::run` with the workload's program translated ahead of time.
+    Native(NativeFn),
+}
+impl Path {
+    fn run(self, mut program_state: ProgramState) -> (ProgramState, Result<(), VmError>) {
+        match self {
+            Path::Step => {
+                let result = program_state.run();
+                (program_state, result)
+            }
+            Path::Engine => {
+                let mut engine = Engine::new(program_state);
+                let result = engine.run();
+                (engine.into_inner(), result)
+            }
+            Path::Native(function) => aot::run_native(program_state, function),
+        }
+    }
+}
+
+// What a workload did: instructions executed and a checksum of its results,
+// which must agree between the two paths.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Work {
+    cycles: u64,
+    checksum: i64,
+}
+
+// Every noun and verb of day 2: ten thousand short runs.
+fn day2_search(program: &Intcode, path: Path) -> Work {
+    let snapshot = program.builder().build().snapshot();
+    let mut work = Work {
+        cycles: 0,
+        checksum: 0,
+    };
+    for noun in 0..100 {
+        for verb in 0..100 {
+            let mut program_state = snapshot.resume();
+            program_state.program.set(1, noun);
+            program_state.program.set(2, verb);
+            let (program_state, result) = path.run(program_state);
+            work.cycles += program_state.cycles;
+            if result.is_ok() {
+                work.checksum = work.checksum.wrapping_add(program_state.program[0]);
+            }
+        }
+    }
+    work
+}
+
+// Both day 5 diagnostics, repeated: mid-sized branchy programs.
+fn day5_diagnostics(program: &Intcode, path: Path) -> Work {
+    let mut work = Work {
+        cycles: 0,
+        checksum: 0,
+    };
+    for _ in 0..2000 {
+        for system in [1, 5] {
+            let (mut program_state, result) = path.run(program.builder().input(system).build());
+            result.expect("Should have been able to run the diagnostic");
+            work.cycles += program_state.cycles;
+            work.checksum = work
+                .checksum
+                .wrapping_add(program_state.drain_outputs().iter().sum());
+        }
+    }
+    work
+}
+
+// Every ordering of the day 7 amplifiers, repeated: many tiny runs.
+fn day7_amplifiers(program: &Intcode, path: Path) -> Work {
+    let mut work = Work {
+        cycles: 0,
+        checksum: 0,
+    };
+    for _ in 0..50 {
+        for phases in (0..5).permutations(5) {
+            let mut signal = 0;
+            for phase in phases {
+                let program_state = program.builder().inputs([phase, signal]).build();
+                let (mut program_state, result) = path.run(program_state);
+                result.expect("Should have been able to run the amplifier");
+                work.cycles += program_state.cycles;
+                signal = program_state
+                    .pop_output()
+                    .expect("Should have been able to read the signal");
+            }
+            work.checksum = work.checksum.wrapping_add(signal);
+        }
+    }
+    work
+}
+
+// One long-running tight loop, also translated as `aot::tight_loop`: it
+// counts the word at 12 down from a million, adding each value to the word
+// at 13.
+fn tight_loop(program: &Intcode, path: Path) -> Work {
+    let (program_state, result) = path.run(program.builder().build());
+    result.expect("Should have been able to run the tight loop");
+    Work {
+        cycles: program_state.cycles,
+        checksum: program_state.program[13],
+    }
+}
+
+type Workload<'a> = Box<dyn Fn(Path) -> Work + 'a>;
+
+fn time<F: Fn(Path) -> Work>(workload: F, path: Path) -> (Duration, Work) {
+    let mut best = Duration::MAX;
+    let mut work = None;
+    for _ in 0..REPEATS {
+        let start = Instant::now();
+        let result = workload(path);
+        best = best.min(start.elapsed());
+        work = Some(result);
+    }
+    (best, work.unwrap())
+}
+
+pub fn bench() {
+    let day2: Intcode =
+        Intcode::from_file("inputs/day2-input.txt").expect("Should have been able to load day 2");
+    let day5: Intcode =
+        Intcode::from_file("inputs/day5-input.txt").expect("Should have been able to load day 5");
+    let day7: Intcode =
+        Intcode::from_file("inputs/day7-input.txt").expect("Should have been able to load day 7");
+    let tight_loop_program: Intcode = Intcode::from_file("inputs/tight-loop.txt")
+        .expect("Should have been able to load the tight loop");
+    // Only programs `build.rs` translates have a native path.
+    let workloads: Vec<(&str, Workload, Option<NativeFn>)> = vec![
+        (
+            "day 2 search",
+            Box::new(|path| day2_search(&day2, path)),
+            None,
+        ),
+        (
+            "day 5 diagnostics",
+            Box::new(|path| day5_diagnostics(&day5, path)),
+            None,
+        ),
+        (
+            "day 7 amplifiers",
+            Box::new(|path| day7_amplifiers(&day7, path)),
+            Some(aot::day7::run),
+        ),
+        (
+            "tight loop",
+            Box::new(|path| tight_loop(&tight_loop_program, path)),
+            Some(aot::tight_loop::run),
+        ),
+    ];
+    println!(
+        "{:<18} {:>12} {:>12} {:>12} {:>12} {:>8} {:>12}",
+        "workload", "cycles", "step path", "engine", "native", "speedup", "engine MIPS"
+    );
+    for (name, workload, native) in workloads {
+        let (step_time, step_work) = time(&workload, Path::Step);
+        let (engine_time, engine_work) = time(&workload, Path::Engine);
+        assert_eq!(step_work, engine_work, "{} gave different results", name);
+        let native_time = match native {
+            Some(function) => {
+                let (native_time, native_work) = time(&workload, Path::Native(function));
+                assert_eq!(step_work, native_work, "{} gave different results", name);
+                format!("{:>9.2} ms", native_time.as_secs_f64() * 1000.0)
+            }
+            None => "-".to_string(),
+        };
+        println!(
+            "{:<18} {:>12} {:>9.2} ms {:>9.2} ms {:>12} {:>7.2}x {:>12.1}",
+            name,
+            engine_work.cycles,
+            step_time.as_secs_f64() * 1000.0,
+            engine_time.as_secs_f64() * 1000.0,
+            native_time,
+            step_time.as_secs_f64() / engine_time.as_secs_f64(),
+            engine_work.cycles as f64 / engine_time.as_secs_f64() / 1e6
+        );
+    }
+}
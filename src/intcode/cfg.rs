@@ -0,0 +1,397 @@
+use crate::intcode::disasm::{decode_at, Line};
+use crate::intcode::loader::Intcode;
+pub use crate::intcode::opcode::decode::EdgeKind;
+use crate::intcode::opcode::{Op, OpCode, ParamType};
+use crate::intcode::word::Word;
+use std::collections::{BTreeMap, BTreeSet};
+use std::ops::Range;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Block<W: Word = i64> {
+    pub start: usize,
+    pub lines: Vec<Line<W>>,
+    // Successor block addresses. A target that does not decode is listed in
+    // `Cfg::invalid` rather than having a block of its own.
+    pub edges: Vec<(usize, EdgeKind)>,
+    // Ends in a jump whose target is read from memory, so where it goes is
+    // not known statically.
+    pub indirect: bool,
+}
+impl<W: Word> Block<W> {
+    // One past the last word of the block.
+    pub fn end(&self) -> usize {
+        self.lines
+            .last()
+            .map_or(self.start, |line| line.address() + line.size())
+    }
+}
+
+// An instruction whose position-mode write target lies inside reachable code,
+// including reachable words that only become valid instructions at run time.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SelfModifyingWrite {
+    pub address: usize,
+    pub target: usize,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cfg<W: Word = i64> {
+    pub blocks: BTreeMap<usize, Block<W>>,
+    // Addresses execution can reach that do not hold a valid instruction.
+    pub invalid: BTreeSet<usize>,
+    // Parts of the image no reachable instruction covers: data, or dead code.
+    pub unreachable: Vec<Range<usize>>,
+    pub self_modifying: Vec<SelfModifyingWrite>,
+}
+
+// `OpCode::successors` for the instruction `decode_at` found at `address`.
+fn successors<W: Word>(
+    image: &[W],
+    address: usize,
+    opcode: &OpCode,
+) -> (Vec<(usize, EdgeKind)>, bool) {
+    let operands = &image[address + 1..address + opcode.get_instruction_size()];
+    opcode.successors(address, operands, W::to_address)
+}
+
+// Follows every path from address 0 and groups what it reaches into basic
+// blocks: straight-line runs entered only at the top and left only at the
+// bottom.
+pub fn build_cfg<W: Word>(image: &[W]) -> Cfg<W> {
+    build_cfg_from(image, &[0])
+}
+// Like `build_cfg`, starting from each of `entries`: useful when the caller
+// knows where indirect jumps can land.
+pub fn build_cfg_from<W: Word>(image: &[W], entries: &[usize]) -> Cfg<W> {
+    let mut instructions: BTreeMap<usize, OpCode> = BTreeMap::new();
+    let mut invalid = BTreeSet::new();
+    let mut leaders: BTreeSet<usize> = entries.iter().copied().collect();
+    let mut pending = entries.to_vec();
+    while let Some(address) = pending.pop() {
+        if instructions.contains_key(&address) || invalid.contains(&address) {
+            continue;
+        }
+        let Some(opcode) = decode_at(image, address) else {
+            invalid.insert(address);
+            continue;
+        };
+        let (edges, _) = successors(image, address, &opcode);
+        let ends_block = matches!(opcode.op(), Op::JumpIfTrue | Op::JumpIfFalse | Op::Halt);
+        for (target, _) in edges {
+            if ends_block {
+                leaders.insert(target);
+            }
+            pending.push(target);
+        }
+        instructions.insert(address, opcode);
+    }
+
+    let mut blocks = BTreeMap::new();
+    for &start in leaders
+        .iter()
+        .filter(|start| instructions.contains_key(start))
+    {
+        let mut block = Block {
+            start,
+            lines: Vec::new(),
+            edges: Vec::new(),
+            indirect: false,
+        };
+        let mut address = start;
+        loop {
+            let opcode = instructions[&address];
+            let size = opcode.get_instruction_size();
+            block.lines.push(Line::Instruction {
+                address,
+                opcode,
+                operands: image[address + 1..address + size].to_vec(),
+            });
+            let (edges, indirect) = successors(image, address, &opcode);
+            let next = address + size;
+            let ends_block = edges.len() != 1
+                || edges[0] != (next, EdgeKind::Fallthrough)
+                || leaders.contains(&next)
+                || !instructions.contains_key(&next);
+            if ends_block {
+                block.edges = edges;
+                block.indirect = indirect;
+                break;
+            }
+            address = next;
+        }
+        blocks.insert(start, block);
+    }
+
+    let mut covered = vec![false; image.len()];
+    for (&address, opcode) in &instructions {
+        for cell in covered
+            .iter_mut()
+            .skip(address)
+            .take(opcode.get_instruction_size())
+        {
+            *cell = true;
+        }
+    }
+    let mut unreachable: Vec<Range<usize>> = Vec::new();
+    for (address, _) in covered.iter().enumerate().filter(|(_, covered)| !**covered) {
+        match unreachable.last_mut() {
+            Some(range) if range.end == address => range.end += 1,
+            _ => unreachable.push(address..address + 1),
+        }
+    }
+
+    let mut self_modifying = Vec::new();
+    for (&address, opcode) in &instructions {
+        if !opcode.op().writes_to_program() {
+            continue;
+        }
+        let target_index = opcode.op().number_of_parameters();
+        if opcode.param_modes()[target_index - 1] != ParamType::Position {
+            continue;
+        }
+        if let Some(target) = image[address + target_index].to_address() {
+            if covered.get(target) == Some(&true) || invalid.contains(&target) {
+                self_modifying.push(SelfModifyingWrite { address, target });
+            }
+        }
+    }
+
+    Cfg {
+        blocks,
+        invalid,
+        unreachable,
+        self_modifying,
+    }
+}
+
+impl<W: Word> Cfg<W> {
+    // The block holding the instruction that covers `address`, if any.
+    pub fn block_containing(&self, address: usize) -> Option<&Block<W>> {
+        self.blocks
+            .range(..=address)
+            .next_back()
+            .map(|(_, block)| block)
+            .filter(|block| address < block.end())
+    }
+    // A Graphviz digraph with one box per block. Jumps are solid edges,
+    // fallthroughs dashed, and writes into code red and dotted.
+    pub fn to_dot(&self) -> String {
+        let mut dot =
+            String::from("digraph intcode {\n    node [shape=box, fontname=monospace];\n");
+        for block in self.blocks.values() {
+            let lines: Vec<String> = block
+                .lines
+                .iter()
+                .map(|line| escape(&line.to_string()))
+                .collect();
+            dot.push_str(&format!(
+                "    b{} [label=\"{}\\l\"];\n",
+                block.start,
+                lines.join("\\l")
+            ));
+            for (target, kind) in &block.edges {
+                let style = match kind {
+                    EdgeKind::Jump => "solid",
+                    EdgeKind::Fallthrough => "dashed",
+                };
+                dot.push_str(&format!(
+                    "    b{} -> {} [style={}];\n",
+                    block.start,
+                    self.node(*target),
+                    style
+                ));
+            }
+            if block.indirect {
+                dot.push_str(&format!(
+                    "    b{} -> indirect{} [style=bold];\n    indirect{} [label=\"?\", shape=circle];\n",
+                    block.start, block.start, block.start
+                ));
+            }
+        }
+        for address in &self.invalid {
+            dot.push_str(&format!(
+                "    invalid{} [label=\"invalid {}\", color=red];\n",
+                address, address
+            ));
+        }
+        for range in &self.unreachable {
+            dot.push_str(&format!(
+                "    data{} [label=\"data {}..{}\", shape=note, color=gray];\n",
+                range.start, range.start, range.end
+            ));
+        }
+        // One edge per pair of blocks, listing every address written.
+        let mut writes: BTreeMap<(usize, String), BTreeSet<usize>> = BTreeMap::new();
+        for write in &self.self_modifying {
+            let Some(from) = self.block_containing(write.address) else {
+                continue;
+            };
+            let to = match self.block_containing(write.target) {
+                Some(block) => format!("b{}", block.start),
+                None => self.node(write.target),
+            };
+            writes
+                .entry((from.start, to))
+                .or_default()
+                .insert(write.target);
+        }
+        for ((from, to), targets) in writes {
+            let targets: Vec<String> = targets
+                .iter()
+                .map(|target| format!("[{}]", target))
+                .collect();
+            dot.push_str(&format!(
+                "    b{} -> {} [style=dotted, color=red, label=\"writes {}\"];\n",
+                from,
+                to,
+                targets.join(", ")
+            ));
+        }
+        dot.push_str("}\n");
+        dot
+    }
+    fn node(&self, address: usize) -> String {
+        match self.blocks.contains_key(&address) {
+            true => format!("b{}", address),
+            false => format!("invalid{}", address),
+        }
+    }
+}
+
+fn escape(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+pub fn cfg(file_path: String) {
+    let program: Intcode =
+        Intcode::from_file(file_path).expect("Should have been able to load the program");
+    print!("{}", build_cfg(&program.image).to_dot());
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::intcode::asm::{assemble, COUNTDOWN};
+    use crate::intcode::cfg::{build_cfg, Cfg, EdgeKind, SelfModifyingWrite};
+    use crate::intcode::loader::Intcode;
+
+    fn cfg_of(source: &str) -> Cfg {
+        build_cfg(&assemble::<i64>(source).unwrap())
+    }
+    fn edges(cfg: &Cfg) -> Vec<(usize, usize, EdgeKind)> {
+        cfg.blocks
+            .values()
+            .flat_map(|block| {
+                block
+                    .edges
+                    .iter()
+                    .map(move |(target, kind)| (block.start, *target, *kind))
+            })
+            .collect()
+    }
+    #[test]
+    fn test_loop() {
+        let cfg = cfg_of(COUNTDOWN);
+        assert_eq!(
+            cfg.blocks.keys().copied().collect::<Vec<_>>(),
+            vec![0, 2, 11]
+        );
+        assert_eq!(
+            edges(&cfg),
+            vec![
+                (0, 2, EdgeKind::Fallthrough),
+                (2, 2, EdgeKind::Jump),
+                (2, 11, EdgeKind::Fallthrough)
+            ]
+        );
+        assert_eq!(cfg.blocks[&2].lines.len(), 3);
+        assert_eq!(cfg.blocks[&2].end(), 11);
+        assert_eq!(cfg.unreachable, vec![12..13]);
+        assert!(cfg.invalid.is_empty());
+        assert!(cfg.self_modifying.is_empty());
+    }
+    #[test]
+    fn test_constant_conditions() {
+        let cfg = cfg_of(
+            "
+                        JT #1, #skip
+                        OUT #1
+                skip:   JF #1, #0
+                        HLT",
+        );
+        assert_eq!(
+            edges(&cfg),
+            vec![(0, 5, EdgeKind::Jump), (5, 8, EdgeKind::Fallthrough)]
+        );
+        assert_eq!(cfg.unreachable, vec![3..5]);
+    }
+    #[test]
+    fn test_indirect_and_invalid() {
+        let cfg = cfg_of(
+            "
+                JT [flag], rb+0
+                JF #0, #123
+                OUT #1
+        flag:   data 1",
+        );
+        assert!(cfg.blocks[&0].indirect);
+        assert_eq!(cfg.invalid, [123].into());
+        assert_eq!(cfg.unreachable, vec![6..9]);
+        assert!(cfg.to_dot().contains("b3 -> invalid123 [style=solid];"));
+    }
+    #[test]
+    fn test_self_modifying_write() {
+        let cfg = cfg_of(
+            "
+                patch:  ADD [x], #3, [x]
+                        JT [again], #second
+                        OUT [x]
+                        HLT
+                second: ADD #0, #0, [again]
+                        ADD #1002, #0, [patch]
+                        JT #1, #patch
+                again:  data 1
+                x:      data 5",
+        );
+        assert_eq!(
+            cfg.self_modifying,
+            vec![SelfModifyingWrite {
+                address: 14,
+                target: 0
+            }]
+        );
+        assert!(cfg
+            .to_dot()
+            .contains("b10 -> b0 [style=dotted, color=red, label=\"writes [0]\"];"));
+    }
+    #[test]
+    fn test_dot() {
+        let dot = cfg_of(COUNTDOWN).to_dot();
+        assert!(dot.starts_with("digraph intcode {\n"));
+        assert!(dot.contains("    b2 [label=\"    2: OUT [12]\\l    4: ADD [12], #-1, [12]\\l    8: JT [12], #2\\l\"];\n"));
+        assert!(dot.contains("    b2 -> b11 [style=dashed];\n"));
+        assert!(dot.contains("    data12 [label=\"data 12..13\", shape=note, color=gray];\n"));
+        assert!(dot.ends_with("}\n"));
+    }
+    #[test]
+    fn test_day5() {
+        // The diagnostic adds its input to word 6, turning an invalid opcode
+        // into the instruction that runs next, so analysis stops there.
+        let program: Intcode = Intcode::from_file("inputs/day5-input.txt").unwrap();
+        let cfg = build_cfg(&program.image);
+        assert_eq!(cfg.invalid, [6].into());
+        assert_eq!(
+            cfg.self_modifying,
+            vec![SelfModifyingWrite {
+                address: 2,
+                target: 6
+            }]
+        );
+        assert!(cfg
+            .to_dot()
+            .contains("b0 -> invalid6 [style=dotted, color=red, label=\"writes [6]\"];"));
+        let day2: Intcode = Intcode::from_file("inputs/day2-input.txt").unwrap();
+        let dot = build_cfg(&day2.image).to_dot();
+        assert_eq!(dot.matches("b0 -> b0 [style=dotted").count(), 1);
+        assert!(dot.contains("label=\"writes [0], [3], [19], [23], "));
+    }
+}
@@ -0,0 +1,430 @@
+use crate::intcode::disasm::line_at;
+use crate::intcode::loader::Intcode;
+use crate::intcode::program::{ProgramState, Status};
+use crate::intcode::word::Word;
+use std::collections::BTreeSet;
+use std::io::{self, BufRead, Write};
+
+const HELP: &str = "\
+step [n]          execute n instructions (default 1)
+back [n]          undo n instructions (default 1)
+continue [n]      run until a breakpoint, watchpoint, input request, halt or
+                  detected infinite loop, for at most n instructions
+                  (default 10000000)
+break <addr>      stop before executing the instruction at addr
+delete <addr>     remove a breakpoint or watchpoint
+watch <addr>      stop whenever the cell at addr changes
+history <addr>    list the recorded writes to addr
+journal <n>       remember the last n instructions for back and history
+list              show breakpoints and watchpoints
+regs              show head, relative base, cycles and queued I/O
+mem <addr> [n]    dump n memory cells (default 8) starting at addr
+disasm [addr] [n] disassemble n instructions (default 5) from addr or head
+input <v>...      queue input values
+quit              end the session
+An empty line repeats the previous command.";
+
+// Words of memory shown per line by `mem`.
+const MEMORY_ROW: usize = 8;
+// Most cells `mem` shows, or instructions `disasm` lists, at once.
+const LISTING_LIMIT: usize = 1 << 12;
+// Instructions remembered for `back` and `history` unless changed with
+// `journal`. With i64 words an entry is 88 bytes, as the tests check.
+pub const DEFAULT_JOURNAL_CAPACITY: usize = 1 << 16;
+// Instructions `continue` runs before handing control back.
+const CONTINUE_LIMIT: usize = 10_000_000;
+
+pub struct Debugger<W: Word = i64> {
+    pub program_state: ProgramState<W>,
+    breakpoints: BTreeSet<usize>,
+    watchpoints: BTreeSet<usize>,
+}
+impl<W: Word> Debugger<W> {
+    pub fn new(mut program_state: ProgramState<W>) -> Self {
+        if program_state.journal.is_none() {
+            program_state.enable_journal(DEFAULT_JOURNAL_CAPACITY);
+        }
+        // Lets `continue` stop a program caught looping without I/O.
+        if program_state.watchdog.is_none() {
+            program_state.enable_watchdog(None);
+        }
+        Debugger {
+            program_state,
+            breakpoints: BTreeSet::new(),
+            watchpoints: BTreeSet::new(),
+        }
+    }
+    // Reads commands until `quit` or the end of `input`.
+    pub fn repl<I: BufRead, O: Write>(&mut self, input: I, mut out: O) -> io::Result<()> {
+        let mut previous = String::new();
+        let mut lines = input.lines();
+        loop {
+            write!(out, "(icdb) ")?;
+            out.flush()?;
+            let Some(line) = lines.next() else {
+                writeln!(out)?;
+                return Ok(());
+            };
+            let line = line?;
+            let command = if line.trim().is_empty() {
+                previous.clone()
+            } else {
+                line
+            };
+            if !self.execute(&command, &mut out)? {
+                return Ok(());
+            }
+            previous = command;
+        }
+    }
+    // Runs a single command. Returns false when the session should end.
+    pub fn execute<O: Write>(&mut self, command: &str, out: &mut O) -> io::Result<bool> {
+        let mut words = command.split_whitespace();
+        let Some(name) = words.next() else {
+            return Ok(true);
+        };
+        let arguments: Vec<&str> = words.collect();
+        // Inputs may be negative, so they are parsed as words rather than
+        // addresses.
+        if (name == "input" || name == "i") && !arguments.is_empty() {
+            self.queue_inputs(&arguments, out)?;
+            return Ok(true);
+        }
+        let numbers: Result<Vec<usize>, _> = arguments.iter().map(|word| word.parse()).collect();
+        let Ok(numbers) = numbers else {
+            writeln!(out, "arguments must be non-negative numbers")?;
+            return Ok(true);
+        };
+        match (name, numbers.as_slice()) {
+            ("step" | "s", []) => self.step(1, out)?,
+            ("step" | "s", [count]) => self.step(*count, out)?,
+            ("back" | "bk", []) => self.back(1, out)?,
+            ("back" | "bk", [count]) => self.back(*count, out)?,
+            ("continue" | "c", []) => self.resume(CONTINUE_LIMIT, out)?,
+            ("continue" | "c", [limit]) => self.resume(*limit, out)?,
+            ("break" | "b", [address]) => {
+                self.breakpoints.insert(*address);
+                writeln!(out, "breakpoint at {}", address)?;
+            }
+            ("watch" | "w", [address]) => {
+                self.watchpoints.insert(*address);
+                writeln!(out, "watching [{}]", address)?;
+            }
+            ("delete" | "d", [address]) => {
+                let removed = self.breakpoints.remove(address) | self.watchpoints.remove(address);
+                if !removed {
+                    writeln!(out, "nothing set at {}", address)?;
+                }
+            }
+            ("list", []) => {
+                writeln!(out, "breakpoints: {:?}", self.breakpoints)?;
+                writeln!(out, "watchpoints: {:?}", self.watchpoints)?;
+            }
+            ("history", [address]) => self.history(*address, out)?,
+            ("journal", [capacity]) => {
+                match &mut self.program_state.journal {
+                    Some(journal) => journal.set_capacity(*capacity),
+                    None => self.program_state.enable_journal(*capacity),
+                }
+                writeln!(out, "remembering the last {} instruction(s)", capacity)?;
+            }
+            ("regs" | "r", []) => self.show_registers(out)?,
+            ("mem" | "x", [address]) => self.dump_memory(*address, MEMORY_ROW, out)?,
+            ("mem" | "x", [address, count]) => self.dump_memory(*address, *count, out)?,
+            ("disasm" | "l", []) => self.list(self.program_state.head, 5, out)?,
+            ("disasm" | "l", [address]) => self.list(*address, 5, out)?,
+            ("disasm" | "l", [address, count]) => self.list(*address, *count, out)?,
+            ("help" | "h", []) => writeln!(out, "{}", HELP)?,
+            ("quit" | "q", []) => return Ok(false),
+            _ => writeln!(out, "unknown command {:?}; try help", command.trim())?,
+        }
+        Ok(true)
+    }
+    fn queue_inputs<O: Write>(&mut self, arguments: &[&str], out: &mut O) -> io::Result<()> {
+        let values: Result<Vec<W>, _> = arguments.iter().map(|word| word.parse()).collect();
+        match values {
+            Ok(values) => {
+                writeln!(out, "queued {} input(s)", values.len())?;
+                self.program_state.extend_inputs(values);
+            }
+            Err(_) => writeln!(out, "inputs must be values like 5 or -3")?,
+        }
+        Ok(())
+    }
+    // Executes one instruction, reporting anything the user asked to hear
+    // about. Returns true if execution should stop.
+    fn single_step<O: Write>(&mut self, out: &mut O) -> io::Result<bool> {
+        let watched: Vec<(usize, W)> = self
+            .watchpoints
+            .iter()
+            .map(|&address| (address, self.program_state.program[address].clone()))
+            .collect();
+        let step = match self.program_state.step() {
+            Ok(Some(step)) => step,
+            Ok(None) => {
+                writeln!(out, "program has halted")?;
+                return Ok(true);
+            }
+            Err(error) => {
+                writeln!(out, "error: {}", error)?;
+                return Ok(true);
+            }
+        };
+        let mut stop = false;
+        match step.status {
+            Some(Status::NeedsInput) => {
+                writeln!(out, "waiting for input at {}", step.address)?;
+                stop = true;
+            }
+            Some(Status::Output(value)) => writeln!(out, "output: {}", value)?,
+            Some(Status::Halted) => {
+                writeln!(out, "halted at {}", step.address)?;
+                stop = true;
+            }
+            None => {}
+        }
+        for (address, old_value) in watched {
+            let new_value = &self.program_state.program[address];
+            if *new_value != old_value {
+                writeln!(
+                    out,
+                    "watch [{}]: {} -> {} (written by {})",
+                    address, old_value, new_value, step.address
+                )?;
+                stop = true;
+            }
+        }
+        Ok(stop)
+    }
+    fn step<O: Write>(&mut self, count: usize, out: &mut O) -> io::Result<()> {
+        for _ in 0..count {
+            writeln!(
+                out,
+                "{}",
+                line_at(&self.program_state.program, self.program_state.head)
+            )?;
+            if self.single_step(out)? {
+                break;
+            }
+        }
+        Ok(())
+    }
+    fn resume<O: Write>(&mut self, limit: usize, out: &mut O) -> io::Result<()> {
+        for executed in 0..limit {
+            let head = self.program_state.head;
+            // Always make progress, even when resuming from a breakpoint.
+            if executed > 0 && self.breakpoints.contains(&head) {
+                writeln!(out, "breakpoint at {}", head)?;
+                return writeln!(out, "{}", line_at(&self.program_state.program, head));
+            }
+            if self.single_step(out)? {
+                return Ok(());
+            }
+        }
+        writeln!(out, "paused after {} instruction(s)", limit)?;
+        writeln!(
+            out,
+            "{}",
+            line_at(&self.program_state.program, self.program_state.head)
+        )
+    }
+    fn back<O: Write>(&mut self, count: usize, out: &mut O) -> io::Result<()> {
+        for _ in 0..count {
+            if !self.program_state.step_back() {
+                writeln!(out, "no earlier instruction recorded")?;
+                break;
+            }
+        }
+        writeln!(
+            out,
+            "{}",
+            line_at(&self.program_state.program, self.program_state.head)
+        )
+    }
+    fn history<O: Write>(&self, address: usize, out: &mut O) -> io::Result<()> {
+        let Some(journal) = &self.program_state.journal else {
+            return Ok(());
+        };
+        let writes: Vec<_> = journal.writes_to(address).collect();
+        if writes.is_empty() {
+            return writeln!(out, "no recorded writes to [{}]", address);
+        }
+        for (index, entry) in writes.iter().enumerate() {
+            // Each write is undone by restoring the value it replaced, so the
+            // value it stored is whatever the next write replaced.
+            let new_value = match writes.get(index + 1) {
+                Some(next) => next.overwritten.as_ref().map(|(_, value)| value.clone()),
+                None => Some(self.program_state.program[address].clone()),
+            };
+            let old_value = entry.overwritten.as_ref().map(|(_, value)| value);
+            if let (Some(old_value), Some(new_value)) = (old_value, new_value) {
+                writeln!(
+                    out,
+                    "cycle {}: {} -> {} (written by {})",
+                    entry.cycle, old_value, new_value, entry.head
+                )?;
+            }
+        }
+        Ok(())
+    }
+    fn show_registers<O: Write>(&self, out: &mut O) -> io::Result<()> {
+        let program_state = &self.program_state;
+        writeln!(out, "head:          {}", program_state.head)?;
+        writeln!(out, "relative base: {}", program_state.relative_base)?;
+        writeln!(out, "cycles:        {}", program_state.cycles)?;
+        writeln!(out, "running:       {}", program_state.running)?;
+        writeln!(out, "inputs:        {:?}", program_state.inputs)?;
+        writeln!(out, "outputs:       {:?}", program_state.outputs)
+    }
+    fn dump_memory<O: Write>(&self, address: usize, count: usize, out: &mut O) -> io::Result<()> {
+        if count > LISTING_LIMIT {
+            return writeln!(out, "at most {} cells at a time", LISTING_LIMIT);
+        }
+        let Some(end) = address.checked_add(count) else {
+            return writeln!(
+                out,
+                "{} cells from {} run past the last address",
+                count, address
+            );
+        };
+        for row_start in (address..end).step_by(MEMORY_ROW) {
+            let row_end = row_start.saturating_add(MEMORY_ROW).min(end);
+            let values: Vec<String> = (row_start..row_end)
+                .map(|cell| self.program_state.program[cell].to_string())
+                .collect();
+            writeln!(out, "{:>5}: {}", row_start, values.join(" "))?;
+        }
+        Ok(())
+    }
+    fn list<O: Write>(&self, address: usize, count: usize, out: &mut O) -> io::Result<()> {
+        if count > LISTING_LIMIT {
+            return writeln!(out, "at most {} instructions at a time", LISTING_LIMIT);
+        }
+        let mut address = address;
+        for _ in 0..count {
+            let line = line_at(&self.program_state.program, address);
+            let marker = if address == self.program_state.head {
+                ">"
+            } else {
+                " "
+            };
+            writeln!(out, "{}{}", marker, line)?;
+            let Some(next) = address.checked_add(line.size()) else {
+                break;
+            };
+            address = next;
+        }
+        Ok(())
+    }
+}
+
+// Takes an optional journal capacity after the program path.
+pub fn debug(file_path: String, arguments: &[String]) {
+    let program: Intcode =
+        Intcode::from_file(file_path).expect("Should have been able to load the program");
+    let capacity = match arguments.first() {
+        Some(capacity) => capacity
+            .parse()
+            .expect("Should have been given a journal capacity"),
+        None => DEFAULT_JOURNAL_CAPACITY,
+    };
+    let mut debugger = Debugger::new(program.builder().journal(capacity).build());
+    let stdin = io::stdin();
+    debugger
+        .repl(stdin.lock(), io::stdout())
+        .expect("Should have been able to use the terminal");
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::intcode::asm::{assemble, COUNTDOWN};
+    use crate::intcode::debugger::Debugger;
+    use crate::intcode::journal::JournalEntry;
+    use crate::intcode::program::ProgramState;
+
+    fn session(source: &str, commands: &str) -> String {
+        let image: Vec<i64> = assemble(source).unwrap();
+        let mut debugger = Debugger::new(ProgramState::new(image));
+        let mut out = Vec::new();
+        debugger.repl(commands.as_bytes(), &mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+    #[test]
+    fn test_step_and_input() {
+        let transcript = session(COUNTDOWN, "step\ninput 2\nstep\n\nregs\n");
+        assert!(transcript.contains("    0: IN [12]\nwaiting for input at 0\n"));
+        assert!(transcript.contains("queued 1 input(s)\n"));
+        // The empty line repeats the previous `step`.
+        assert!(transcript.contains("    2: OUT [12]\noutput: 2\n"));
+        assert!(transcript.contains("head:          4\n"));
+        assert!(transcript.contains("cycles:        2\n"));
+    }
+    #[test]
+    fn test_breakpoint_and_continue() {
+        let transcript = session(COUNTDOWN, "input 3\nbreak 8\ncontinue\nc\ndelete 8\nc\nc\n");
+        let breakpoint = "breakpoint at 8\n    8: JT [12], #2\n";
+        assert_eq!(transcript.matches(breakpoint).count(), 2);
+        assert!(transcript.contains("output: 3\n"));
+        assert!(transcript.contains("output: 1\nhalted at 11\n"));
+        assert!(transcript.contains("program has halted\n"));
+    }
+    #[test]
+    fn test_watchpoint() {
+        let transcript = session(COUNTDOWN, "input 2\nwatch 12\nc\nc\nmem 10 3\n");
+        assert!(transcript.contains("watch [12]: 0 -> 2 (written by 0)\n"));
+        assert!(transcript.contains("output: 2\nwatch [12]: 2 -> 1 (written by 4)\n"));
+        assert!(transcript.contains("   10: 2 99 1\n"));
+    }
+    #[test]
+    fn test_back_and_history() {
+        let transcript = session(
+            COUNTDOWN,
+            "input 2\nstep 4\nback 3\nregs\nhistory 12\nback 9\n",
+        );
+        assert!(transcript.contains("(icdb)     2: OUT [12]\n(icdb) head:          2\n"));
+        assert!(transcript.contains("outputs:       []\n"));
+        assert!(transcript.contains("cycle 0: 0 -> 2 (written by 0)\n"));
+        assert!(!transcript.contains("written by 4"));
+        assert!(transcript.contains("no earlier instruction recorded\n    0: IN [12]\n"));
+    }
+    #[test]
+    fn test_continue_is_bounded() {
+        let transcript = session("loop: JT #1, #loop", "continue\n");
+        assert!(transcript.contains(
+            "error: infinite loop at 0: instructions 0..=0 repeat every 1 cycles without I/O\n"
+        ));
+        // A counter never repeats a state, so only the limit stops it.
+        let transcript = session("loop: ADD [n], #1, [n]\nJT #1, #loop\nn: data 0", "c 5\n");
+        assert!(transcript.contains("paused after 5 instruction(s)\n    4: JT #1, #0\n"));
+    }
+    #[test]
+    fn test_journal_setting() {
+        assert_eq!(std::mem::size_of::<JournalEntry>(), 88);
+        let transcript = session(COUNTDOWN, "input 3\njournal 2\nstep 5\nback 3\n");
+        assert!(transcript.contains("remembering the last 2 instruction(s)\n"));
+        assert!(transcript.contains("no earlier instruction recorded\n    8: JT [12], #2\n"));
+    }
+    #[test]
+    fn test_disasm_and_errors() {
+        let transcript = session(COUNTDOWN, "disasm 0 2\nbreak x\nfrobnicate\nquit\nregs\n");
+        assert!(transcript.contains(">    0: IN [12]\n     2: OUT [12]\n"));
+        assert!(transcript.contains("arguments must be non-negative numbers\n"));
+        assert!(transcript.contains("unknown command \"frobnicate\"; try help\n"));
+        let transcript = session(
+            COUNTDOWN,
+            "mem 18446744073709551615 10\ndisasm 18446744073709551615 2\n",
+        );
+        assert!(
+            transcript.contains("10 cells from 18446744073709551615 run past the last address\n")
+        );
+        assert!(transcript.contains(" 18446744073709551615: data 0\n(icdb)"));
+        assert!(!transcript.contains("head:"));
+        let transcript = session(
+            COUNTDOWN,
+            "mem 0 18446744073709551615\ndisasm 0 4097\nmem 0 4096\n",
+        );
+        assert!(transcript.contains("at most 4096 cells at a time\n"));
+        assert!(transcript.contains("at most 4096 instructions at a time\n"));
+        assert!(transcript.contains(" 4088: 0 0 0 0 0 0 0 0\n"));
+    }
+}
@@ -0,0 +1,184 @@
+use crate::intcode::loader::Intcode;
+use crate::intcode::memory::Memory;
+use crate::intcode::opcode::{decode, OpCode};
+use crate::intcode::word::Word;
+use std::fmt;
+
+// Consecutive data words are grouped, this many to a line.
+const DATA_PER_LINE: usize = 8;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Line<W: Word = i64> {
+    Instruction {
+        address: usize,
+        opcode: OpCode,
+        operands: Vec<W>,
+    },
+    Data {
+        address: usize,
+        values: Vec<W>,
+    },
+}
+impl<W: Word> Line<W> {
+    pub fn address(&self) -> usize {
+        match self {
+            Line::Instruction { address, .. } | Line::Data { address, .. } => *address,
+        }
+    }
+    pub fn size(&self) -> usize {
+        match self {
+            Line::Instruction { opcode, .. } => opcode.get_instruction_size(),
+            Line::Data { values, .. } => values.len(),
+        }
+    }
+}
+impl<W: Word> fmt::Display for Line<W> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Line::Instruction {
+                address,
+                opcode,
+                operands,
+            } => write!(f, "{:>5}: {}", address, opcode.format(operands)),
+            Line::Data { address, values } => {
+                let values: Vec<String> = values.iter().map(|value| value.to_string()).collect();
+                write!(f, "{:>5}: data {}", address, values.join(", "))
+            }
+        }
+    }
+}
+
+// The instruction starting at `address`, if the word there is a valid opcode
+// whose parameters fit in the image and which never writes to an immediate.
+pub fn decode_at<W: Word>(image: &[W], address: usize) -> Option<OpCode> {
+    decode::decode_at(image, address, W::to_i64)
+}
+
+// The line starting at `address` of a running program's memory. Unlike
+// `disassemble` there is no image end, so missing operands read as zero.
+pub fn line_at<W: Word>(memory: &Memory<W>, address: usize) -> Line<W> {
+    match OpCode::parse(&memory[address], address) {
+        Ok(opcode)
+            if !opcode.writes_to_immediate()
+                && address.checked_add(opcode.get_instruction_size()).is_some() =>
+        {
+            let operands = (1..opcode.get_instruction_size())
+                .map(|offset| memory[address + offset].clone())
+                .collect();
+            Line::Instruction {
+                address,
+                opcode,
+                operands,
+            }
+        }
+        _ => Line::Data {
+            address,
+            values: vec![memory[address].clone()],
+        },
+    }
+}
+
+// A linear sweep: anything that decodes is shown as an instruction, and
+// everything else as data. So that the listing assembles back to the same
+// image, words carrying mode digits the op ignores, such as 20104 for
+// `OUT #..`, are shown as data too.
+pub fn disassemble<W: Word>(image: &[W]) -> Vec<Line<W>> {
+    let mut lines: Vec<Line<W>> = Vec::new();
+    let mut address = 0;
+    while address < image.len() {
+        let canonical = decode_at(image, address)
+            .filter(|opcode| image[address].to_i64() == Some(opcode.code()));
+        if let Some(opcode) = canonical {
+            let size = opcode.get_instruction_size();
+            lines.push(Line::Instruction {
+                address,
+                opcode,
+                operands: image[address + 1..address + size].to_vec(),
+            });
+            address += size;
+            continue;
+        }
+        match lines.last_mut() {
+            Some(Line::Data {
+                address: start,
+                values,
+            }) if *start + values.len() == address && values.len() < DATA_PER_LINE => {
+                values.push(image[address].clone())
+            }
+            _ => lines.push(Line::Data {
+                address,
+                values: vec![image[address].clone()],
+            }),
+        }
+        address += 1;
+    }
+    lines
+}
+
+pub fn disasm(file_path: String) {
+    let program: Intcode =
+        Intcode::from_file(file_path).expect("Should have been able to load the program");
+    for line in disassemble(&program.image) {
+        println!("{}", line);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::intcode::disasm::disassemble;
+
+    fn render(image: &[i64]) -> Vec<String> {
+        disassemble(image)
+            .iter()
+            .map(|line| line.to_string())
+            .collect()
+    }
+    #[test]
+    fn test_modes() {
+        assert_eq!(
+            render(&[1002, 4, 3, 4, 33]),
+            vec!["    0: MUL [4], #3, [4]", "    4: data 33"]
+        );
+        assert_eq!(
+            render(&[109, -3, 21201, -1, 7, 3, 204, 0, 99]),
+            vec![
+                "    0: ARB #-3",
+                "    2: ADD rb-1, #7, rb+3",
+                "    6: OUT rb+0",
+                "    8: HLT"
+            ]
+        );
+    }
+    #[test]
+    fn test_all_mnemonics() {
+        let image = [
+            1, 0, 0, 0, 2, 0, 0, 0, 3, 0, 4, 0, 5, 0, 0, 6, 0, 0, 7, 0, 0, 0, 8, 0, 0, 0, 9, 0, 99,
+        ];
+        let mnemonics: Vec<String> = render(&image)
+            .iter()
+            .map(|line| line[7..].split(' ').next().unwrap().to_string())
+            .collect();
+        assert_eq!(
+            mnemonics,
+            vec!["ADD", "MUL", "IN", "OUT", "JT", "JF", "LT", "EQ", "ARB", "HLT"]
+        );
+    }
+    #[test]
+    fn test_non_canonical_words_are_data() {
+        assert_eq!(
+            render(&[20104, 7, 99]),
+            vec!["    0: data 20104, 7", "    2: HLT"]
+        );
+    }
+    #[test]
+    fn test_data_regions() {
+        assert_eq!(
+            render(&[99, 0, -5, 11101, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1]),
+            vec![
+                "    0: HLT",
+                "    1: data 0, -5, 11101, 0, 0, 0, 0, 0",
+                "    9: data 0, 0, 0, 0, 0, 1"
+            ]
+        );
+    }
+}
@@ -0,0 +1,193 @@
+use crate::intcode::error::VmError;
+use crate::intcode::opcode::OpCode;
+use crate::intcode::program::{ProgramState, Status};
+use crate::intcode::word::Word;
+
+// Decoded instructions are cached for addresses below this; anything beyond
+// is decoded every time it runs.
+const MAX_CACHED_ADDRESS: usize = 1 << 20;
+
+// Runs a `ProgramState` with each instruction word decoded once and cached
+// by address. The cache entry for an address is dropped whenever the program
+// writes there, so self-modifying code still behaves. Traced, journaled or
+// watched machines fall back to `ProgramState::update`, which records what
+// they need.
+#[derive(Debug)]
+pub struct Engine<W: Word = i64> {
+    program_state: ProgramState<W>,
+    decoded: Vec<Option<OpCode>>,
+}
+impl<W: Word> Engine<W> {
+    pub fn new(program_state: ProgramState<W>) -> Self {
+        let cached = program_state.program.len().min(MAX_CACHED_ADDRESS);
+        Engine {
+            program_state,
+            decoded: vec![None; cached],
+        }
+    }
+    pub fn state(&self) -> &ProgramState<W> {
+        &self.program_state
+    }
+    // The caller may rewrite memory through this, so the cache is dropped.
+    pub fn state_mut(&mut self) -> &mut ProgramState<W> {
+        self.decoded.clear();
+        &mut self.program_state
+    }
+    pub fn into_inner(self) -> ProgramState<W> {
+        self.program_state
+    }
+    pub fn push_input(&mut self, value: W) {
+        self.program_state.push_input(value);
+    }
+    pub fn extend_inputs<I: IntoIterator<Item = W>>(&mut self, values: I) {
+        self.program_state.extend_inputs(values);
+    }
+    pub fn pop_output(&mut self) -> Option<W> {
+        self.program_state.pop_output()
+    }
+    pub fn drain_outputs(&mut self) -> Vec<W> {
+        self.program_state.drain_outputs()
+    }
+    fn decode(&mut self, address: usize) -> Result<OpCode, VmError> {
+        if let Some(Some(opcode)) = self.decoded.get(address) {
+            return Ok(*opcode);
+        }
+        let opcode = OpCode::parse(&self.program_state.program[address], address)?;
+        if address < MAX_CACHED_ADDRESS {
+            if self.decoded.len() <= address {
+                self.decoded.resize(address + 1, None);
+            }
+            self.decoded[address] = Some(opcode);
+        }
+        Ok(opcode)
+    }
+    // Behaves exactly like `ProgramState::update`.
+    pub fn update(&mut self) -> Result<Status<W>, VmError> {
+        if self.program_state.recording() {
+            return self.program_state.update();
+        }
+        let mut parameters: [W; 3] = Default::default();
+        while self.program_state.running {
+            let head = self.program_state.head;
+            let opcode = self.decode(head)?;
+            let op = opcode.op();
+            let count = op.number_of_parameters();
+            opcode.resolve_parameters_into(&self.program_state, &mut parameters)?;
+            let mut next_head = head + opcode.get_instruction_size();
+            let status = op.execute(
+                &mut self.program_state,
+                &parameters[..count],
+                &mut next_head,
+            )?;
+            if status == Some(Status::NeedsInput) {
+                return Ok(Status::NeedsInput);
+            }
+            if op.writes_to_program() {
+                let written = self.program_state.program.resolve(&parameters[count - 1]);
+                if let Some(entry) = written.and_then(|address| self.decoded.get_mut(address)) {
+                    *entry = None;
+                }
+            }
+            self.program_state.cycles += 1;
+            self.program_state.head = next_head;
+            if let Some(status) = status {
+                return Ok(status);
+            }
+        }
+        Ok(Status::Halted)
+    }
+    // Behaves exactly like `ProgramState::run`.
+    pub fn run(&mut self) -> Result<(), VmError> {
+        loop {
+            match self.update()? {
+                Status::Halted => return Ok(()),
+                Status::NeedsInput => {
+                    return Err(VmError::InputUnderflow {
+                        head: self.program_state.head,
+                    })
+                }
+                Status::Output(_) => {}
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::intcode::asm::assemble;
+    use crate::intcode::engine::Engine;
+    use crate::intcode::error::VmError;
+    use crate::intcode::loader::{Intcode, ProgramBuilder};
+    use crate::intcode::program::{ProgramState, Status};
+
+    fn both(image: Vec<i64>, inputs: Vec<i64>) -> (ProgramState, ProgramState) {
+        let mut slow = ProgramBuilder::new(image.clone())
+            .inputs(inputs.clone())
+            .build();
+        let slow_result = slow.run();
+        let mut fast = Engine::new(ProgramBuilder::new(image).inputs(inputs).build());
+        assert_eq!(fast.run(), slow_result);
+        (slow, fast.into_inner())
+    }
+    #[test]
+    fn test_matches_step_path() {
+        let day5: Intcode = Intcode::from_file("inputs/day5-input.txt").unwrap();
+        for input in [1, 5, 8] {
+            let (slow, fast) = both(day5.image.clone(), vec![input]);
+            assert_eq!(fast.outputs, slow.outputs);
+            assert_eq!(fast.cycles, slow.cycles);
+            assert_eq!(fast.head, slow.head);
+            assert_eq!(fast.program, slow.program);
+        }
+        // A quine using the relative base, from the day 9 examples.
+        let quine = vec![
+            109, 1, 204, -1, 1001, 100, 1, 100, 1008, 100, 16, 101, 1006, 101, 0, 99,
+        ];
+        let (_, fast) = both(quine.clone(), Vec::new());
+        assert_eq!(fast.outputs, quine);
+    }
+    #[test]
+    fn test_self_modifying_code() {
+        // The ADD at `patch` runs once, is rewritten into a MUL and runs
+        // again, so a stale cache would print 11 rather than 24.
+        let image = assemble(
+            "
+                patch:  ADD [x], #3, [x]
+                        JT [again], #second
+                        OUT [x]
+                        HLT
+                second: ADD #0, #0, [again]
+                        ADD #1002, #0, [patch]
+                        JT #1, #patch
+                again:  data 1
+                x:      data 5",
+        )
+        .unwrap();
+        let (_, fast) = both(image, Vec::new());
+        assert_eq!(fast.outputs, vec![24]);
+    }
+    #[test]
+    fn test_pauses_for_input() {
+        let mut engine = Engine::new(ProgramState::new(vec![3, 5, 4, 5, 99, 0]));
+        assert_eq!(engine.update(), Ok(Status::NeedsInput));
+        assert_eq!(engine.state().head, 0);
+        engine.push_input(7);
+        assert_eq!(engine.update(), Ok(Status::Output(7)));
+        assert_eq!(engine.update(), Ok(Status::Halted));
+        assert_eq!(engine.state().cycles, 3);
+        assert_eq!(
+            Engine::new(ProgramState::new(vec![3, 0, 99])).run(),
+            Err(VmError::InputUnderflow { head: 0 })
+        );
+    }
+    #[test]
+    fn test_state_mut_drops_cache() {
+        let mut engine = Engine::new(ProgramState::new(vec![104, 7, 99]));
+        assert_eq!(engine.update(), Ok(Status::Output(7)));
+        // `OUT #7` becomes `OUT [7]`, and address 7 holds zero.
+        let program_state = engine.state_mut();
+        program_state.head = 0;
+        program_state.program.set(0, 4);
+        assert_eq!(engine.update(), Ok(Status::Output(0)));
+    }
+}
@@ -0,0 +1,102 @@
+use std::fmt;
+
+// Every variant records `head`, the address of the instruction that failed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum VmError {
+    UnknownOpcode {
+        head: usize,
+        code: String,
+    },
+    // `parameter` counts from 1, matching the puzzle text.
+    BadParameterMode {
+        head: usize,
+        parameter: usize,
+        mode: i32,
+    },
+    ImmediateWrite {
+        head: usize,
+    },
+    InputUnderflow {
+        head: usize,
+    },
+    // `value` is the offending word as the program held it, e.g. "-1".
+    BadAddress {
+        head: usize,
+        value: String,
+    },
+    Overflow {
+        head: usize,
+    },
+    // The machine came back to a state it was in `period` instructions
+    // earlier without doing any I/O, so it would repeat forever. `start` and
+    // `end` are the lowest and highest instruction addresses in the loop.
+    InfiniteLoop {
+        head: usize,
+        period: u64,
+        start: usize,
+        end: usize,
+    },
+    // More than `budget` instructions ran without any I/O, most recently
+    // between `start` and `end`.
+    CycleBudget {
+        head: usize,
+        budget: u64,
+        start: usize,
+        end: usize,
+    },
+}
+impl fmt::Display for VmError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            VmError::UnknownOpcode { head, code } => {
+                write!(f, "unknown opcode {} at {}", code, head)
+            }
+            VmError::BadParameterMode {
+                head,
+                parameter,
+                mode,
+            } => write!(
+                f,
+                "instruction at {} has invalid mode {} for parameter {}",
+                head, mode, parameter
+            ),
+            VmError::ImmediateWrite { head } => {
+                write!(
+                    f,
+                    "instruction at {} writes to an immediate parameter",
+                    head
+                )
+            }
+            VmError::InputUnderflow { head } => {
+                write!(f, "instruction at {} read from an empty input", head)
+            }
+            VmError::BadAddress { head, value } => {
+                write!(f, "instruction at {} used invalid address {}", head, value)
+            }
+            VmError::Overflow { head } => {
+                write!(f, "arithmetic overflow in instruction at {}", head)
+            }
+            VmError::InfiniteLoop {
+                head,
+                period,
+                start,
+                end,
+            } => write!(
+                f,
+                "infinite loop at {}: instructions {}..={} repeat every {} cycles without I/O",
+                head, start, end, period
+            ),
+            VmError::CycleBudget {
+                head,
+                budget,
+                start,
+                end,
+            } => write!(
+                f,
+                "instruction at {} exceeded the budget of {} cycles without I/O, running {}..={}",
+                head, budget, start, end
+            ),
+        }
+    }
+}
+impl std::error::Error for VmError {}
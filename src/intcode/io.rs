@@ -0,0 +1,162 @@
+use crate::intcode::word::Word;
+use std::collections::VecDeque;
+use std::fmt;
+use std::io::{self, BufRead, Write};
+use std::sync::mpsc::{Receiver, Sender};
+
+// Where a program's `Save` instructions take input from and its `Read`
+// instructions send output to.
+pub trait IntcodeIo<W: Word> {
+    // None means no input is available yet, which pauses the VM with
+    // `Status::NeedsInput`.
+    fn read(&mut self) -> Option<W>;
+    fn write(&mut self, value: W);
+}
+impl<W: Word> fmt::Debug for dyn IntcodeIo<W> + Send {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("IntcodeIo")
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct VecIo<W: Word> {
+    pub inputs: VecDeque<W>,
+    pub outputs: Vec<W>,
+}
+impl<W: Word> VecIo<W> {
+    pub fn new(inputs: Vec<W>) -> Self {
+        VecIo {
+            inputs: inputs.into(),
+            outputs: Vec::new(),
+        }
+    }
+}
+impl<W: Word> IntcodeIo<W> for VecIo<W> {
+    fn read(&mut self) -> Option<W> {
+        self.inputs.pop_front()
+    }
+    fn write(&mut self, value: W) {
+        self.outputs.push(value);
+    }
+}
+
+pub struct FnIo<R, O> {
+    read: R,
+    write: O,
+}
+impl<R, O> FnIo<R, O> {
+    pub fn new(read: R, write: O) -> Self {
+        FnIo { read, write }
+    }
+}
+impl<W: Word, R: FnMut() -> Option<W>, O: FnMut(W)> IntcodeIo<W> for FnIo<R, O> {
+    fn read(&mut self) -> Option<W> {
+        (self.read)()
+    }
+    fn write(&mut self, value: W) {
+        (self.write)(value)
+    }
+}
+
+// Reads block until a value arrives. A disconnected sender reads as
+// starvation and outputs to a dropped receiver are discarded.
+#[derive(Debug)]
+pub struct ChannelIo<W: Word> {
+    receiver: Receiver<W>,
+    senders: Vec<Sender<W>>,
+}
+impl<W: Word> ChannelIo<W> {
+    pub fn new(receiver: Receiver<W>, sender: Sender<W>) -> Self {
+        ChannelIo::broadcast(receiver, vec![sender])
+    }
+    // Every output goes to each of `senders`.
+    pub fn broadcast(receiver: Receiver<W>, senders: Vec<Sender<W>>) -> Self {
+        ChannelIo { receiver, senders }
+    }
+}
+impl<W: Word> IntcodeIo<W> for ChannelIo<W> {
+    fn read(&mut self) -> Option<W> {
+        self.receiver.recv().ok()
+    }
+    fn write(&mut self, value: W) {
+        for sender in &self.senders {
+            let _ = sender.send(value.clone());
+        }
+    }
+}
+
+// Reads one value per line from stdin and prints each output on its own line.
+#[derive(Debug, Default)]
+pub struct StdIo;
+impl<W: Word> IntcodeIo<W> for StdIo {
+    fn read(&mut self) -> Option<W> {
+        let stdin = io::stdin();
+        loop {
+            print!("> ");
+            io::stdout().flush().ok()?;
+            let mut line = String::new();
+            if stdin.lock().read_line(&mut line).ok()? == 0 {
+                return None;
+            }
+            match line.trim().parse() {
+                Ok(value) => return Some(value),
+                Err(_) => eprintln!("Not a valid value: {:?}", line.trim()),
+            }
+        }
+    }
+    fn write(&mut self, value: W) {
+        println!("{}", value);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::intcode::io::{ChannelIo, FnIo, IntcodeIo, VecIo};
+    use std::sync::mpsc::channel;
+
+    #[test]
+    fn test_vec_io() {
+        let mut io = VecIo::new(vec![1_i64, 2]);
+        assert_eq!(io.read(), Some(1));
+        io.write(5);
+        assert_eq!(io.read(), Some(2));
+        assert_eq!(io.read(), None);
+        assert_eq!(io.outputs, vec![5]);
+    }
+    #[test]
+    fn test_fn_io() {
+        let mut written = Vec::new();
+        {
+            let mut io = FnIo::new(|| Some(7_i64), |value| written.push(value));
+            assert_eq!(io.read(), Some(7));
+            io.write(3);
+        }
+        assert_eq!(written, vec![3]);
+    }
+    #[test]
+    fn test_channel_io() {
+        let (input_sender, input_receiver) = channel();
+        let (output_sender, output_receiver) = channel();
+        let mut io = ChannelIo::new(input_receiver, output_sender);
+        input_sender.send(4_i64).unwrap();
+        assert_eq!(io.read(), Some(4));
+        io.write(9);
+        assert_eq!(output_receiver.recv(), Ok(9));
+        drop(input_sender);
+        assert_eq!(io.read(), None);
+    }
+    #[test]
+    fn test_channel_broadcast() {
+        let (_input_sender, input_receiver) = channel::<i64>();
+        let (first_sender, first_receiver) = channel();
+        let (second_sender, second_receiver) = channel();
+        let mut io = ChannelIo::broadcast(input_receiver, vec![first_sender, second_sender]);
+        io.write(6);
+        assert_eq!(first_receiver.recv(), Ok(6));
+        assert_eq!(second_receiver.recv(), Ok(6));
+        // A receiver going away does not stop the others hearing.
+        drop(second_receiver);
+        io.write(7);
+        assert_eq!(first_receiver.recv(), Ok(7));
+    }
+}
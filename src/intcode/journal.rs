@@ -0,0 +1,194 @@
+use crate::intcode::word::Word;
+use std::collections::VecDeque;
+
+// Everything needed to undo one executed instruction.
+#[derive(Debug, Clone, PartialEq)]
+pub struct JournalEntry<W: Word = i64> {
+    // Instructions executed before this one.
+    pub cycle: u64,
+    pub head: usize,
+    pub relative_base: W,
+    // The cell the instruction wrote and the value it held beforehand.
+    pub overwritten: Option<(usize, W)>,
+    // The input an input instruction consumed.
+    pub input: Option<W>,
+    // The value an output instruction produced.
+    pub output: Option<W>,
+    pub halted: bool,
+}
+
+// The most recent entries, oldest first. Once `capacity` entries are held the
+// oldest are forgotten, so long runs can be journaled in bounded memory.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Journal<W: Word = i64> {
+    entries: VecDeque<JournalEntry<W>>,
+    capacity: usize,
+}
+impl<W: Word> Journal<W> {
+    pub fn new(capacity: usize) -> Self {
+        Journal {
+            entries: VecDeque::new(),
+            capacity,
+        }
+    }
+    pub fn push(&mut self, entry: JournalEntry<W>) {
+        if self.capacity == 0 {
+            return;
+        }
+        if self.entries.len() == self.capacity {
+            self.entries.pop_front();
+        }
+        self.entries.push_back(entry);
+    }
+    // Forgets the oldest entries if there are now too many.
+    pub fn set_capacity(&mut self, capacity: usize) {
+        self.capacity = capacity;
+        while self.entries.len() > capacity {
+            self.entries.pop_front();
+        }
+    }
+    pub fn pop(&mut self) -> Option<JournalEntry<W>> {
+        self.entries.pop_back()
+    }
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+    // The journaled instructions that wrote to `address`, oldest first.
+    pub fn writes_to(&self, address: usize) -> impl Iterator<Item = &JournalEntry<W>> {
+        self.entries.iter().filter(
+            move |entry| matches!(entry.overwritten, Some((written, _)) if written == address),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::intcode::asm::{assemble, COUNTDOWN};
+    use crate::intcode::io::VecIo;
+    use crate::intcode::journal::{Journal, JournalEntry};
+    use crate::intcode::loader::ProgramBuilder;
+    use crate::intcode::program::{ProgramState, Status};
+
+    fn journaled(source: &str, inputs: Vec<i64>) -> ProgramState {
+        ProgramBuilder::new(assemble(source).unwrap())
+            .inputs(inputs)
+            .journal(1000)
+            .build()
+    }
+    #[test]
+    fn test_step_back_to_start() {
+        let mut program_state = journaled(COUNTDOWN, vec![3]);
+        let initial = program_state.program.clone();
+        program_state.run().unwrap();
+        assert_eq!(program_state.drain_outputs(), vec![3, 2, 1]);
+        // The outputs have been taken, so there are none left to retract.
+        while program_state.step_back() {}
+        assert_eq!(program_state.head, 0);
+        assert_eq!(program_state.cycles, 0);
+        assert!(program_state.running);
+        assert_eq!(program_state.program, initial);
+        assert_eq!(program_state.inputs, vec![3]);
+        program_state.run().unwrap();
+        assert_eq!(program_state.drain_outputs(), vec![3, 2, 1]);
+    }
+    #[test]
+    fn test_step_back_retracts_output() {
+        let mut program_state = journaled(COUNTDOWN, vec![2]);
+        program_state.run().unwrap();
+        assert_eq!(program_state.outputs, vec![2, 1]);
+        assert!(program_state.step_back());
+        assert!(program_state.running);
+        assert_eq!(program_state.head, 11);
+        for _ in 0..3 {
+            assert!(program_state.step_back());
+        }
+        assert_eq!(program_state.head, 2);
+        assert_eq!(program_state.outputs, vec![2]);
+        assert_eq!(program_state.program[12], 1);
+    }
+    #[test]
+    fn test_step_back_leaves_taken_outputs() {
+        let mut program_state = journaled(COUNTDOWN, vec![2]);
+        program_state.run().unwrap();
+        assert_eq!(program_state.pop_output(), Some(2));
+        program_state.outputs.push_back(7);
+        // Back over the halt and the last pass, which output 1; the 7 at the
+        // back is not that output, so it stays.
+        for _ in 0..4 {
+            assert!(program_state.step_back());
+        }
+        assert_eq!(program_state.head, 2);
+        assert_eq!(program_state.outputs, vec![1, 7]);
+    }
+    #[test]
+    fn test_step_back_through_io() {
+        let mut program_state = ProgramBuilder::new(assemble(COUNTDOWN).unwrap())
+            .io(VecIo::new(vec![2]))
+            .journal(100)
+            .build();
+        program_state.run().unwrap();
+        while program_state.step_back() {}
+        assert_eq!(program_state.head, 0);
+        // Neither the input read from `io` nor its outputs come back.
+        assert!(program_state.inputs.is_empty());
+        assert!(program_state.outputs.is_empty());
+        assert_eq!(program_state.update(), Ok(Status::NeedsInput));
+    }
+    #[test]
+    fn test_step_back_relative_base() {
+        let mut program_state = journaled("ARB #5\nARB #-2\nHLT", Vec::new());
+        program_state.run().unwrap();
+        assert_eq!(program_state.relative_base, 3);
+        program_state.step_back();
+        program_state.step_back();
+        assert_eq!(program_state.relative_base, 5);
+    }
+    #[test]
+    fn test_writes_to() {
+        let mut program_state = journaled(COUNTDOWN, vec![2]);
+        program_state.run().unwrap();
+        let journal = program_state.journal.as_ref().unwrap();
+        let writers: Vec<(usize, i64)> = journal
+            .writes_to(12)
+            .map(|entry| (entry.head, entry.overwritten.unwrap().1))
+            .collect();
+        assert_eq!(writers, vec![(0, 0), (4, 2), (4, 1)]);
+    }
+    #[test]
+    fn test_capacity() {
+        let mut program_state = ProgramBuilder::new(assemble(COUNTDOWN).unwrap())
+            .input(5)
+            .journal(4)
+            .build();
+        program_state.run().unwrap();
+        assert_eq!(program_state.cycles, 17);
+        assert_eq!(program_state.journal.as_ref().unwrap().len(), 4);
+        let mut steps = 0;
+        while program_state.step_back() {
+            steps += 1;
+        }
+        assert_eq!(steps, 4);
+        assert_eq!(program_state.cycles, 13);
+        assert_eq!(program_state.head, 2);
+    }
+    #[test]
+    fn test_zero_capacity() {
+        let mut journal = Journal::new(0);
+        journal.push(JournalEntry {
+            cycle: 0,
+            head: 0,
+            relative_base: 0,
+            overwritten: None,
+            input: None,
+            output: None,
+            halted: false,
+        });
+        assert!(journal.is_empty());
+    }
+}
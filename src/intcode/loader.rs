@@ -0,0 +1,192 @@
+use crate::intcode::io::IntcodeIo;
+use crate::intcode::program::ProgramState;
+use crate::intcode::trace::TraceSink;
+use crate::intcode::word::Word;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::str::FromStr;
+
+#[derive(Debug)]
+pub enum LoadError {
+    Io(io::Error),
+    // `index` counts comma-separated tokens from 0, so it is also the
+    // address the value would have been loaded at.
+    InvalidToken { index: usize, token: String },
+    // `line` counts from 1.
+    BadSnapshot { line: usize, reason: String },
+}
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LoadError::Io(error) => write!(f, "could not read program: {}", error),
+            LoadError::InvalidToken { index, token } => {
+                write!(f, "invalid value {:?} at token {}", token, index)
+            }
+            LoadError::BadSnapshot { line, reason } => {
+                write!(f, "bad snapshot at line {}: {}", line, reason)
+            }
+        }
+    }
+}
+impl std::error::Error for LoadError {}
+impl From<io::Error> for LoadError {
+    fn from(error: io::Error) -> Self {
+        LoadError::Io(error)
+    }
+}
+
+// A program image as read from a puzzle input, ready to be run any number of
+// times.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Intcode<W: Word = i64> {
+    pub image: Vec<W>,
+}
+impl<W: Word> Intcode<W> {
+    pub fn new(image: Vec<W>) -> Self {
+        Intcode { image }
+    }
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self, LoadError> {
+        fs::read_to_string(path)?.parse()
+    }
+    pub fn builder(&self) -> ProgramBuilder<W> {
+        ProgramBuilder::new(self.image.clone())
+    }
+}
+impl<W: Word> FromStr for Intcode<W> {
+    type Err = LoadError;
+    fn from_str(contents: &str) -> Result<Self, LoadError> {
+        let mut image = Vec::new();
+        let tokens: Vec<&str> = contents.split(',').map(|s| s.trim()).collect();
+        for (index, token) in tokens.iter().enumerate() {
+            // A trailing comma or newline leaves one empty token at the end.
+            if token.is_empty() && index + 1 == tokens.len() {
+                continue;
+            }
+            match token.parse() {
+                Ok(value) => image.push(value),
+                Err(_) => {
+                    return Err(LoadError::InvalidToken {
+                        index,
+                        token: token.to_string(),
+                    })
+                }
+            }
+        }
+        Ok(Intcode { image })
+    }
+}
+
+pub struct ProgramBuilder<W: Word = i64> {
+    program_state: ProgramState<W>,
+}
+impl<W: Word> ProgramBuilder<W> {
+    pub fn new(image: Vec<W>) -> Self {
+        ProgramBuilder {
+            program_state: ProgramState::new(image),
+        }
+    }
+    pub fn input(mut self, value: W) -> Self {
+        self.program_state.push_input(value);
+        self
+    }
+    pub fn inputs<I: IntoIterator<Item = W>>(mut self, values: I) -> Self {
+        self.program_state.extend_inputs(values);
+        self
+    }
+    // Overwrites a cell of the image before the program starts, e.g. the
+    // noun and verb at addresses 1 and 2.
+    pub fn patch(mut self, address: usize, value: W) -> Self {
+        self.program_state.program.set(address, value);
+        self
+    }
+    // Addresses at or beyond `words` are reported as `VmError::BadAddress`.
+    pub fn memory_limit(mut self, words: usize) -> Self {
+        self.program_state.program.set_limit(Some(words));
+        self
+    }
+    pub fn io<IO: IntcodeIo<W> + Send + 'static>(mut self, io: IO) -> Self {
+        self.program_state.set_io(io);
+        self
+    }
+    pub fn trace<T: TraceSink<W> + Send + 'static>(mut self, trace: T) -> Self {
+        self.program_state.set_trace(trace);
+        self
+    }
+    pub fn journal(mut self, capacity: usize) -> Self {
+        self.program_state.enable_journal(capacity);
+        self
+    }
+    pub fn watchdog(mut self, budget: Option<u64>) -> Self {
+        self.program_state.enable_watchdog(budget);
+        self
+    }
+    pub fn build(self) -> ProgramState<W> {
+        self.program_state
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::intcode::error::VmError;
+    use crate::intcode::loader::{Intcode, LoadError};
+
+    #[test]
+    fn test_parse() {
+        let program: Intcode = "1,0, 0,3,\n99,-7\n".parse().unwrap();
+        assert_eq!(program.image, vec![1, 0, 0, 3, 99, -7]);
+    }
+    #[test]
+    fn test_parse_error_reports_token() {
+        match "1,2,x3,4".parse::<Intcode>() {
+            Err(LoadError::InvalidToken { index, token }) => {
+                assert_eq!(index, 2);
+                assert_eq!(token, "x3");
+            }
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+    #[test]
+    fn test_empty_tokens() {
+        // Only a trailing empty token is skipped; one in the middle would
+        // shift every later value to a lower address.
+        let program: Intcode = "1,2,\n".parse().unwrap();
+        assert_eq!(program.image, vec![1, 2]);
+        match "1,,2".parse::<Intcode>() {
+            Err(LoadError::InvalidToken { index, token }) => {
+                assert_eq!(index, 1);
+                assert_eq!(token, "");
+            }
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+    #[test]
+    fn test_missing_file() {
+        assert!(matches!(
+            Intcode::<i64>::from_file("inputs/no-such-file.txt"),
+            Err(LoadError::Io(_))
+        ));
+    }
+    #[test]
+    fn test_builder() {
+        let program: Intcode = "1,0,0,0,4,0,3,0,4,0,99".parse().unwrap();
+        let mut program_state = program.builder().patch(1, 4).patch(2, 4).input(6).build();
+        program_state.run().unwrap();
+        assert_eq!(program_state.drain_outputs(), vec![8, 6]);
+        assert_eq!(program.image[1], 0);
+    }
+    #[test]
+    fn test_memory_limit() {
+        let program: Intcode = "1101,1,1,100,99".parse().unwrap();
+        let mut program_state = program.builder().memory_limit(64).build();
+        assert_eq!(
+            program_state.run(),
+            Err(VmError::BadAddress {
+                head: 0,
+                value: "100".to_string()
+            })
+        );
+        assert!(program.builder().memory_limit(128).build().run().is_ok());
+    }
+}
@@ -0,0 +1,298 @@
+use crate::intcode::word::Word;
+use std::collections::HashMap;
+use std::ops::{Index, IndexMut};
+use std::sync::Arc;
+
+// Writes this far past the end of the dense image go to the sparse map
+// instead of growing the vector.
+const MAX_DENSE_GROWTH: usize = 1 << 16;
+// The dense image is split into pages of this many words.
+const PAGE_SHIFT: usize = 10;
+const PAGE_SIZE: usize = 1 << PAGE_SHIFT;
+
+#[derive(Debug, Clone)]
+enum Page<W: Word> {
+    // Only this memory holds the page, so it is written in place.
+    Owned(Vec<W>),
+    // Shared with snapshots or other machines, and copied on the first write.
+    Shared(Arc<Vec<W>>),
+}
+impl<W: Word> Page<W> {
+    fn cells(&self) -> &[W] {
+        match self {
+            Page::Owned(cells) => cells,
+            Page::Shared(cells) => cells,
+        }
+    }
+    fn cells_mut(&mut self) -> &mut [W] {
+        if let Page::Shared(shared) = self {
+            *self = Page::Owned(std::mem::take(Arc::make_mut(shared)));
+        }
+        match self {
+            Page::Owned(cells) => cells,
+            Page::Shared(_) => unreachable!("page was just made owned"),
+        }
+    }
+    fn share(&mut self) {
+        if let Page::Owned(cells) = self {
+            *self = Page::Shared(Arc::new(std::mem::take(cells)));
+        }
+    }
+}
+
+// Cloning copies every page that is not shared; call `share_pages` first to
+// make a copy that costs almost nothing until either side writes.
+#[derive(Debug, Clone)]
+pub struct Memory<W: Word> {
+    pages: Vec<Page<W>>,
+    // Addresses below this are held in `pages`; the rest of the last page is
+    // padding.
+    dense_len: usize,
+    sparse: Arc<HashMap<usize, W>>,
+    zero: W,
+    limit: Option<usize>,
+}
+impl<W: Word> Memory<W> {
+    pub fn new(image: Vec<W>) -> Self {
+        let mut memory = Memory {
+            pages: Vec::new(),
+            dense_len: 0,
+            sparse: Arc::new(HashMap::new()),
+            zero: W::default(),
+            limit: None,
+        };
+        for chunk in image.chunks(PAGE_SIZE) {
+            let mut page = chunk.to_vec();
+            page.resize(PAGE_SIZE, W::default());
+            memory.pages.push(Page::Owned(page));
+        }
+        memory.dense_len = image.len();
+        memory
+    }
+    // Marks every page as shared, so clones share them until written.
+    pub fn share_pages(&mut self) {
+        for page in self.pages.iter_mut() {
+            page.share();
+        }
+    }
+    pub fn set_limit(&mut self, limit: Option<usize>) {
+        self.limit = limit;
+    }
+    pub fn limit(&self) -> Option<usize> {
+        self.limit
+    }
+    // Converts a word to an address, rejecting values that cannot index memory.
+    pub fn resolve(&self, address: &W) -> Option<usize> {
+        address
+            .to_address()
+            .filter(|&address| self.limit.is_none_or(|limit| address < limit))
+    }
+    pub fn get(&self, address: usize) -> &W {
+        if address < self.dense_len {
+            return &self.pages[address >> PAGE_SHIFT].cells()[address & (PAGE_SIZE - 1)];
+        }
+        self.sparse.get(&address).unwrap_or(&self.zero)
+    }
+    pub fn set(&mut self, address: usize, value: W) {
+        *self.get_mut(address) = value;
+    }
+    fn get_mut(&mut self, address: usize) -> &mut W {
+        if address >= self.dense_len && address - self.dense_len < MAX_DENSE_GROWTH {
+            self.grow(address + 1);
+        }
+        if address < self.dense_len {
+            let page = self.pages[address >> PAGE_SHIFT].cells_mut();
+            return &mut page[address & (PAGE_SIZE - 1)];
+        }
+        Arc::make_mut(&mut self.sparse).entry(address).or_default()
+    }
+    fn grow(&mut self, dense_len: usize) {
+        while self.pages.len() << PAGE_SHIFT < dense_len {
+            self.pages.push(Page::Owned(vec![W::default(); PAGE_SIZE]));
+        }
+        self.dense_len = dense_len;
+        if self.sparse.keys().any(|&address| address < dense_len) {
+            let pages = &mut self.pages;
+            Arc::make_mut(&mut self.sparse).retain(|&address, value| {
+                if address < dense_len {
+                    let page = pages[address >> PAGE_SHIFT].cells_mut();
+                    page[address & (PAGE_SIZE - 1)] = std::mem::take(value);
+                    false
+                } else {
+                    true
+                }
+            });
+        }
+    }
+    // One past the highest address that has been loaded or written.
+    pub fn len(&self) -> usize {
+        let sparse_end = self.sparse.keys().max().map_or(0, |address| address + 1);
+        self.dense_len.max(sparse_end)
+    }
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+    pub fn to_vec(&self) -> Vec<W> {
+        (0..self.len())
+            .map(|address| self.get(address).clone())
+            .collect()
+    }
+    // The contiguous image starting at address 0, without the far-off cells
+    // `sparse_cells` returns.
+    pub fn dense_cells(&self) -> impl Iterator<Item = &W> {
+        self.pages
+            .iter()
+            .flat_map(|page| page.cells().iter())
+            .take(self.dense_len)
+    }
+    // Appends the dense image to `cells` a page at a time, which is much
+    // faster than collecting `dense_cells`.
+    pub fn copy_dense(&self, cells: &mut Vec<W>) {
+        let mut remaining = self.dense_len;
+        for page in &self.pages {
+            let count = remaining.min(PAGE_SIZE);
+            cells.extend_from_slice(&page.cells()[..count]);
+            remaining -= count;
+        }
+    }
+    // Overwrites the start of the dense image with `cells`. Pages whose
+    // contents are unchanged are left alone, so they stay shared.
+    pub fn store_dense(&mut self, cells: &[W]) {
+        assert!(cells.len() <= self.dense_len);
+        for (page, chunk) in self.pages.iter_mut().zip(cells.chunks(PAGE_SIZE)) {
+            if page.cells()[..chunk.len()] != *chunk {
+                page.cells_mut()[..chunk.len()].clone_from_slice(chunk);
+            }
+        }
+    }
+    // Cells written far past the dense image, in address order.
+    pub fn sparse_cells(&self) -> Vec<(usize, &W)> {
+        let mut cells: Vec<(usize, &W)> = self
+            .sparse
+            .iter()
+            .map(|(&address, value)| (address, value))
+            .collect();
+        cells.sort_by_key(|&(address, _)| address);
+        cells
+    }
+}
+impl<W: Word> From<Vec<W>> for Memory<W> {
+    fn from(image: Vec<W>) -> Self {
+        Memory::new(image)
+    }
+}
+impl<W: Word> Index<usize> for Memory<W> {
+    type Output = W;
+    fn index(&self, address: usize) -> &W {
+        self.get(address)
+    }
+}
+impl<W: Word> IndexMut<usize> for Memory<W> {
+    fn index_mut(&mut self, address: usize) -> &mut W {
+        self.get_mut(address)
+    }
+}
+impl<W: Word> PartialEq for Memory<W> {
+    fn eq(&self, other: &Self) -> bool {
+        let dense_len = self.dense_len.max(other.dense_len);
+        (0..dense_len)
+            .chain(self.sparse.keys().copied())
+            .chain(other.sparse.keys().copied())
+            .all(|address| self.get(address) == other.get(address))
+    }
+}
+impl<W: Word> PartialEq<Vec<W>> for Memory<W> {
+    fn eq(&self, other: &Vec<W>) -> bool {
+        self.len() == other.len()
+            && other
+                .iter()
+                .enumerate()
+                .all(|(address, value)| self.get(address) == value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::intcode::memory::{Memory, Page};
+    use std::sync::Arc;
+
+    #[test]
+    fn test_read_past_end_is_zero() {
+        let memory = Memory::new(vec![1_i64, 2, 3]);
+        assert_eq!(memory[3], 0);
+        assert_eq!(memory[1_000_000], 0);
+        assert_eq!(memory.len(), 3);
+    }
+    #[test]
+    fn test_write_past_end_grows() {
+        let mut memory = Memory::new(vec![1_i64, 2, 3]);
+        memory.set(5, 7);
+        assert_eq!(memory, vec![1, 2, 3, 0, 0, 7]);
+    }
+    #[test]
+    fn test_far_write_is_sparse() {
+        let mut memory = Memory::new(vec![1_i64]);
+        memory.set(1 << 40, 9);
+        assert_eq!(memory[1 << 40], 9);
+        assert_eq!(memory.len(), (1 << 40) + 1);
+        memory.set(3, 4);
+        assert_eq!(memory[3], 4);
+        assert_eq!(memory[1 << 40], 9);
+    }
+    #[test]
+    fn test_resolve_rejects_negative() {
+        let memory = Memory::new(vec![0_i64]);
+        assert_eq!(memory.resolve(&-1), None);
+        assert_eq!(memory.resolve(&12), Some(12));
+    }
+    #[test]
+    fn test_clones_share_pages_until_written() {
+        let same_page = |a: &Page<i64>, b: &Page<i64>| match (a, b) {
+            (Page::Shared(a), Page::Shared(b)) => Arc::ptr_eq(a, b),
+            _ => false,
+        };
+        let image: Vec<i64> = (0..3000).collect();
+        let mut original = Memory::new(image.clone());
+        assert!(!same_page(&original.pages[1], &original.clone().pages[1]));
+        original.share_pages();
+        let copy = original.clone();
+        assert!(same_page(&original.pages[1], &copy.pages[1]));
+        original.set(1500, -1);
+        assert!(matches!(original.pages[1], Page::Owned(_)));
+        assert!(same_page(&original.pages[0], &copy.pages[0]));
+        assert_eq!(original[1500], -1);
+        assert_eq!(copy, image);
+    }
+    #[test]
+    fn test_dense_copy_round_trip() {
+        let image: Vec<i64> = (0..3000).collect();
+        let mut memory = Memory::new(image.clone());
+        memory.share_pages();
+        let copy = memory.clone();
+        let mut cells = Vec::new();
+        memory.copy_dense(&mut cells);
+        assert_eq!(cells, image);
+        cells[2500] = -1;
+        memory.store_dense(&cells);
+        assert_eq!(memory[2500], -1);
+        assert!(matches!(memory.pages[0], Page::Shared(_)));
+        assert!(matches!(memory.pages[2], Page::Owned(_)));
+        assert_eq!(copy, image);
+    }
+    #[test]
+    fn test_growth_across_pages() {
+        let mut memory = Memory::new(vec![1_i64; 1000]);
+        memory.set(5000, 2);
+        assert_eq!(memory.len(), 5001);
+        assert_eq!(memory[4999], 0);
+        assert_eq!(memory.dense_cells().count(), 5001);
+        memory.set(100_000, 3);
+        assert_eq!(memory.sparse_cells(), vec![(100_000, &3)]);
+        memory.set(60_000, 4);
+        assert_eq!(memory.sparse_cells().len(), 1);
+        // Growing the dense image over a sparse cell moves it into a page.
+        memory.set(100_001, 5);
+        assert_eq!(memory.sparse_cells(), vec![]);
+        assert_eq!(memory[100_000], 3);
+    }
+}
@@ -0,0 +1,541 @@
+use crate::intcode::error::VmError;
+use crate::intcode::io::IntcodeIo;
+use crate::intcode::loader::ProgramBuilder;
+use crate::intcode::program::ProgramState;
+use std::collections::VecDeque;
+use std::fmt;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+// Packets sent here go to the NAT rather than to a machine.
+pub const NAT_ADDRESS: usize = 255;
+// What a machine reads when no packet is waiting for it.
+const NO_PACKET: i64 = -1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Packet {
+    pub source: usize,
+    pub destination: usize,
+    pub x: i64,
+    pub y: i64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Event {
+    // A machine sent a packet. Packets to addresses no machine has, other
+    // than the NAT's, are dropped.
+    Sent(Packet),
+    // The network went idle and the NAT resent the last packet it was sent
+    // to machine 0.
+    Wake(Packet),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum NetworkError {
+    Machine { address: usize, error: VmError },
+    // The network went idle with nothing for the NAT to send.
+    Stalled,
+    Halted,
+}
+impl fmt::Display for NetworkError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            NetworkError::Machine { address, error } => {
+                write!(f, "machine {} failed: {}", address, error)
+            }
+            NetworkError::Stalled => write!(f, "the network is idle and the NAT has no packet"),
+            NetworkError::Halted => write!(f, "every machine has halted"),
+        }
+    }
+}
+impl std::error::Error for NetworkError {}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Scheduler {
+    // Each machine in turn runs for a slice of cycles, all on the calling
+    // thread. Deterministic.
+    RoundRobin,
+    // Every machine runs on its own thread.
+    Threaded,
+}
+
+// The packet queues all machines share.
+#[derive(Debug)]
+struct Switch {
+    queues: Vec<VecDeque<i64>>,
+    // Reads of `NO_PACKET` by each machine since any packet last moved.
+    idle_polls: Vec<u32>,
+    // Machines part way through writing a packet, which counts as traffic.
+    sending: Vec<bool>,
+    nat: bool,
+    nat_packet: Option<Packet>,
+    events: VecDeque<Event>,
+}
+impl Switch {
+    fn receive(&mut self, address: usize) -> i64 {
+        match self.queues[address].pop_front() {
+            Some(value) => {
+                self.idle_polls.fill(0);
+                value
+            }
+            None => {
+                self.idle_polls[address] = self.idle_polls[address].saturating_add(1);
+                NO_PACKET
+            }
+        }
+    }
+    fn start_packet(&mut self, address: usize) {
+        self.sending[address] = true;
+    }
+    fn send(&mut self, packet: Packet) {
+        self.idle_polls.fill(0);
+        self.sending[packet.source] = false;
+        self.events.push_back(Event::Sent(packet));
+        if let Some(queue) = self.queues.get_mut(packet.destination) {
+            queue.extend([packet.x, packet.y]);
+        } else if self.nat && packet.destination == NAT_ADDRESS {
+            self.nat_packet = Some(packet);
+        }
+    }
+    fn is_idle(&self, idle_polls: u32) -> bool {
+        self.queues.iter().all(|queue| queue.is_empty())
+            && !self.sending.contains(&true)
+            && self.idle_polls.iter().all(|&polls| polls >= idle_polls)
+    }
+    // Has the NAT wake machine 0, or returns false if it has nothing to send.
+    fn wake(&mut self) -> bool {
+        let Some(packet) = self.nat_packet.filter(|_| !self.queues.is_empty()) else {
+            return false;
+        };
+        let packet = Packet {
+            source: NAT_ADDRESS,
+            destination: 0,
+            ..packet
+        };
+        self.idle_polls.fill(0);
+        self.queues[0].extend([packet.x, packet.y]);
+        self.events.push_back(Event::Wake(packet));
+        true
+    }
+}
+
+// A machine's connection to the switch. Its first read is its own address;
+// after that it reads packets as X then Y, or `NO_PACKET`. It writes packets
+// as destination, X, Y.
+#[derive(Debug)]
+struct NetIo {
+    address: usize,
+    booted: bool,
+    switch: Arc<Mutex<Switch>>,
+    outgoing: Vec<i64>,
+}
+impl IntcodeIo<i64> for NetIo {
+    fn read(&mut self) -> Option<i64> {
+        if !self.booted {
+            self.booted = true;
+            return Some(self.address as i64);
+        }
+        Some(self.switch.lock().unwrap().receive(self.address))
+    }
+    fn write(&mut self, value: i64) {
+        self.outgoing.push(value);
+        if let [destination, x, y] = self.outgoing[..] {
+            self.outgoing.clear();
+            // A negative destination cannot be delivered anywhere.
+            let destination = usize::try_from(destination).unwrap_or(usize::MAX);
+            self.switch.lock().unwrap().send(Packet {
+                source: self.address,
+                destination,
+                x,
+                y,
+            });
+        } else if self.outgoing.len() == 1 {
+            self.switch.lock().unwrap().start_packet(self.address);
+        }
+    }
+}
+
+// What the scheduler does between slices: hands events to `watch`, and has
+// the NAT wake an idle network. Some when the run is over.
+fn supervise<T, F: FnMut(&Event) -> Option<T>>(
+    switch: &Mutex<Switch>,
+    idle_polls: u32,
+    watch: &mut F,
+    halted: bool,
+) -> Option<Result<T, NetworkError>> {
+    let mut switch = switch.lock().unwrap();
+    let stalled = switch.is_idle(idle_polls) && !switch.wake();
+    while let Some(event) = switch.events.pop_front() {
+        if let Some(result) = watch(&event) {
+            return Some(Ok(result));
+        }
+    }
+    if stalled {
+        return Some(Err(NetworkError::Stalled));
+    }
+    halted.then_some(Err(NetworkError::Halted))
+}
+
+// Runs a machine for up to `slice` cycles. Machines on the network never
+// wait for input, since a read with nothing queued returns `NO_PACKET`.
+fn run_slice(program_state: &mut ProgramState, slice: u64) -> Result<(), VmError> {
+    let end = program_state.cycles + slice;
+    while program_state.running && program_state.cycles < end {
+        program_state.run_for(end - program_state.cycles)?;
+    }
+    Ok(())
+}
+
+pub struct NetworkBuilder {
+    image: Vec<i64>,
+    machines: usize,
+    nat: bool,
+    scheduler: Scheduler,
+    slice: u64,
+    idle_polls: u32,
+}
+impl NetworkBuilder {
+    pub fn new(image: Vec<i64>) -> Self {
+        NetworkBuilder {
+            image,
+            machines: 50,
+            nat: true,
+            scheduler: Scheduler::RoundRobin,
+            slice: 1000,
+            idle_polls: 2,
+        }
+    }
+    pub fn machines(mut self, count: usize) -> Self {
+        self.machines = count;
+        self
+    }
+    // Without a NAT, packets to `NAT_ADDRESS` are dropped and an idle
+    // network has stalled.
+    pub fn nat(mut self, nat: bool) -> Self {
+        self.nat = nat;
+        self
+    }
+    pub fn scheduler(mut self, scheduler: Scheduler) -> Self {
+        self.scheduler = scheduler;
+        self
+    }
+    // Cycles each machine runs per turn, or between checks for being
+    // stopped when threaded.
+    pub fn slice(mut self, cycles: u64) -> Self {
+        self.slice = cycles.max(1);
+        self
+    }
+    // The network counts as idle once every queue is empty, no machine is
+    // part way through writing a packet and every machine has found no
+    // packet this many times since traffic last moved.
+    pub fn idle_polls(mut self, polls: u32) -> Self {
+        self.idle_polls = polls;
+        self
+    }
+    pub fn build(self) -> Network {
+        let switch = Arc::new(Mutex::new(Switch {
+            queues: vec![VecDeque::new(); self.machines],
+            idle_polls: vec![0; self.machines],
+            sending: vec![false; self.machines],
+            nat: self.nat,
+            nat_packet: None,
+            events: VecDeque::new(),
+        }));
+        let machines = (0..self.machines)
+            .map(|address| {
+                ProgramBuilder::new(self.image.clone())
+                    .io(NetIo {
+                        address,
+                        booted: false,
+                        switch: switch.clone(),
+                        outgoing: Vec::new(),
+                    })
+                    .build()
+            })
+            .collect();
+        Network {
+            machines,
+            switch,
+            scheduler: self.scheduler,
+            slice: self.slice,
+            idle_polls: self.idle_polls,
+        }
+    }
+}
+
+// Copies of one program, each at its own address, exchanging packets.
+#[derive(Debug)]
+pub struct Network {
+    pub machines: Vec<ProgramState>,
+    switch: Arc<Mutex<Switch>>,
+    scheduler: Scheduler,
+    slice: u64,
+    idle_polls: u32,
+}
+impl Network {
+    // Runs the network, passing each event to `watch` until it returns
+    // Some. Can be called again to carry on from where it stopped.
+    pub fn run_until<T, F: FnMut(&Event) -> Option<T>>(
+        &mut self,
+        watch: F,
+    ) -> Result<T, NetworkError> {
+        match self.scheduler {
+            Scheduler::RoundRobin => self.run_round_robin(watch),
+            Scheduler::Threaded => self.run_threaded(watch),
+        }
+    }
+    fn run_round_robin<T, F: FnMut(&Event) -> Option<T>>(
+        &mut self,
+        mut watch: F,
+    ) -> Result<T, NetworkError> {
+        loop {
+            for (address, machine) in self.machines.iter_mut().enumerate() {
+                run_slice(machine, self.slice)
+                    .map_err(|error| NetworkError::Machine { address, error })?;
+            }
+            let halted = self.machines.iter().all(|machine| !machine.running);
+            if let Some(result) = supervise(&self.switch, self.idle_polls, &mut watch, halted) {
+                return result;
+            }
+        }
+    }
+    // Machines run until told to stop, while this thread watches the
+    // switch. The first machine to fail stops the rest.
+    fn run_threaded<T, F: FnMut(&Event) -> Option<T>>(
+        &mut self,
+        mut watch: F,
+    ) -> Result<T, NetworkError> {
+        let stop = AtomicBool::new(false);
+        let failure: Mutex<Option<NetworkError>> = Mutex::new(None);
+        let slice = self.slice;
+        thread::scope(|scope| {
+            let handles: Vec<_> = self
+                .machines
+                .iter_mut()
+                .enumerate()
+                .map(|(address, machine)| {
+                    let (stop, failure) = (&stop, &failure);
+                    scope.spawn(move || {
+                        while machine.running && !stop.load(Ordering::Relaxed) {
+                            if let Err(error) = run_slice(machine, slice) {
+                                *failure.lock().unwrap() =
+                                    Some(NetworkError::Machine { address, error });
+                                stop.store(true, Ordering::Relaxed);
+                            }
+                        }
+                    })
+                })
+                .collect();
+            let result = loop {
+                thread::yield_now();
+                // A failing machine records why before stopping the others,
+                // so once they have all finished any failure is visible.
+                let halted = handles.iter().all(|handle| handle.is_finished());
+                if let Some(error) = failure.lock().unwrap().take() {
+                    break Err(error);
+                }
+                if let Some(result) = supervise(&self.switch, self.idle_polls, &mut watch, halted) {
+                    break result;
+                }
+            };
+            stop.store(true, Ordering::Relaxed);
+            result
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::intcode::asm::assemble;
+    use crate::intcode::error::VmError;
+    use crate::intcode::network::{
+        Event, NetworkBuilder, NetworkError, Packet, Scheduler, NAT_ADDRESS,
+    };
+
+    // Machine 0 starts a packet down the line and then ignores everything.
+    // Every other machine adds to the packet and passes it on; the last of
+    // the four sends it to the NAT.
+    const RELAY: &str = "
+                IN [addr]
+                JT [addr], #wait
+                OUT #1
+                OUT #1
+                OUT #1
+        ignore: IN [x]
+                JT #1, #ignore
+        wait:   IN [x]
+                EQ [x], #-1, [t]
+                JT [t], #wait
+                IN [y]
+                ADD [addr], #1, [dest]
+                EQ [dest], #4, [t]
+                JF [t], #send
+                ADD #255, #0, [dest]
+        send:   OUT [dest]
+                ADD [x], #1, [x]
+                OUT [x]
+                ADD [y], [x], [y]
+                OUT [y]
+                JT #1, #wait
+        addr:   data 0
+        x:      data 0
+        y:      data 0
+        dest:   data 0
+        t:      data 0";
+
+    fn relay(scheduler: Scheduler) -> NetworkBuilder {
+        NetworkBuilder::new(assemble(RELAY).unwrap())
+            .machines(4)
+            .scheduler(scheduler)
+    }
+    // The first packet sent to the NAT, and the first Y the NAT sends
+    // twice in a row.
+    fn answers(scheduler: Scheduler) -> (Packet, i64) {
+        let mut network = relay(scheduler).build();
+        let first = network
+            .run_until(|event| match event {
+                Event::Sent(packet) if packet.destination == NAT_ADDRESS => Some(*packet),
+                _ => None,
+            })
+            .unwrap();
+        let mut last_y = None;
+        let repeated = network
+            .run_until(|event| match event {
+                Event::Wake(packet) if last_y == Some(packet.y) => Some(packet.y),
+                Event::Wake(packet) => {
+                    last_y = Some(packet.y);
+                    None
+                }
+                _ => None,
+            })
+            .unwrap();
+        (first, repeated)
+    }
+
+    #[test]
+    fn test_round_robin() {
+        let (first, repeated) = answers(Scheduler::RoundRobin);
+        assert_eq!(
+            first,
+            Packet {
+                source: 3,
+                destination: NAT_ADDRESS,
+                x: 4,
+                y: 10
+            }
+        );
+        assert_eq!(repeated, 10);
+    }
+    #[test]
+    fn test_threaded() {
+        let (first, repeated) = answers(Scheduler::Threaded);
+        assert_eq!((first.x, first.y), (4, 10));
+        assert_eq!(repeated, 10);
+    }
+    #[test]
+    fn test_events_in_order() {
+        let mut network = relay(Scheduler::RoundRobin).build();
+        let mut sent = Vec::new();
+        let wake = network
+            .run_until(|event| match event {
+                Event::Sent(packet) => {
+                    sent.push((packet.source, packet.destination));
+                    None
+                }
+                Event::Wake(packet) => Some(*packet),
+            })
+            .unwrap();
+        assert_eq!(sent, vec![(0, 1), (1, 2), (2, 3), (3, NAT_ADDRESS)]);
+        assert_eq!(
+            (wake.source, wake.destination, wake.y),
+            (NAT_ADDRESS, 0, 10)
+        );
+    }
+    #[test]
+    fn test_stalls_without_nat() {
+        for scheduler in [Scheduler::RoundRobin, Scheduler::Threaded] {
+            let mut network = relay(scheduler).nat(false).build();
+            assert_eq!(
+                network.run_until(|_| None::<()>),
+                Err(NetworkError::Stalled)
+            );
+        }
+    }
+    #[test]
+    fn test_partly_written_packet_is_traffic() {
+        // After checking for packets, machine 0 writes the NAT's address
+        // and takes a long time over the rest of the packet.
+        let image = assemble(
+            "
+                    IN [addr]
+                    IN [t]
+                    IN [t]
+                    IN [t]
+                    JT [addr], #poll
+                    OUT #255
+            wait:   ADD [n], #-1, [n]
+                    JT [n], #wait
+                    OUT #3
+                    OUT #7
+            poll:   IN [t]
+                    JT #1, #poll
+            n:      data 100000
+            addr:   data 0
+            t:      data 0",
+        )
+        .unwrap();
+        for scheduler in [Scheduler::RoundRobin, Scheduler::Threaded] {
+            let mut network = NetworkBuilder::new(image.clone())
+                .machines(3)
+                .scheduler(scheduler)
+                .build();
+            assert_eq!(
+                network.run_until(|event| match event {
+                    Event::Sent(packet) => Some(*packet),
+                    Event::Wake(_) => None,
+                }),
+                Ok(Packet {
+                    source: 0,
+                    destination: NAT_ADDRESS,
+                    x: 3,
+                    y: 7
+                })
+            );
+        }
+    }
+    #[test]
+    fn test_machine_failure() {
+        // Machine 2 runs off into an invalid opcode.
+        let image = assemble(
+            "
+                    IN [addr]
+                    EQ [addr], #2, [t]
+                    JT [t], #crash
+            loop:   IN [t]
+                    JT #1, #loop
+            crash:  data 42
+            addr:   data 0
+            t:      data 0",
+        )
+        .unwrap();
+        for scheduler in [Scheduler::RoundRobin, Scheduler::Threaded] {
+            let mut network = NetworkBuilder::new(image.clone())
+                .machines(3)
+                .scheduler(scheduler)
+                .build();
+            assert_eq!(
+                network.run_until(|_| None::<()>),
+                Err(NetworkError::Machine {
+                    address: 2,
+                    error: VmError::UnknownOpcode {
+                        head: 14,
+                        code: "42".to_string()
+                    }
+                })
+            );
+        }
+        let mut network = NetworkBuilder::new(vec![99]).machines(2).build();
+        assert_eq!(network.run_until(|_| None::<()>), Err(NetworkError::Halted));
+    }
+}
@@ -1,149 +1,181 @@
-use crate::intcode::program::ProgramState;
-#[derive(Debug, PartialEq)]
-pub enum Op {
-    Add,
-    Mult,
-    Save,
-    Read,
-    JumpIfTrue,
-    JumpIfFalse,
-    LessThan,
-    Equals,
-    Halt,
-}
+use crate::intcode::error::VmError;
+use crate::intcode::program::{ProgramState, Status};
+use crate::intcode::word::Word;
+
+pub mod decode;
+
+use decode::DecodeError;
+pub use decode::{Op, OpCode, ParamType};
+
 impl Op {
-    fn from_digits(code: i32) -> Self {
-        match code {
-            1 => Op::Add,
-            2 => Op::Mult,
-            3 => Op::Save,
-            4 => Op::Read,
-            5 => Op::JumpIfTrue,
-            6 => Op::JumpIfFalse,
-            7 => Op::LessThan,
-            8 => Op::Equals,
-            99 => Op::Halt,
-            _ => {
-                todo!()
-            }
-        }
-    }
-    fn writes_to_program(&self) -> bool {
-        matches!(
-            self,
-            Op::Add | Op::Mult | Op::Save | Op::LessThan | Op::Equals
-        )
-    }
-    fn number_of_parameters(&self) -> usize {
-        match self {
-            Op::Add => 3,
-            Op::Mult => 3,
-            Op::Save => 1,
-            Op::Read => 1,
-            Op::Halt => 0,
-            Op::JumpIfTrue => 2,
-            Op::JumpIfFalse => 2,
-            Op::LessThan => 3,
-            Op::Equals => 3,
-        }
-    }
-    fn execute(&self, program_state: &mut ProgramState, parameters: &Vec<i32>) -> bool {
-        if parameters.len() != self.number_of_parameters() {
-            todo!();
-        }
+    pub(crate) fn execute<W: Word>(
+        &self,
+        program_state: &mut ProgramState<W>,
+        parameters: &[W],
+        next_head: &mut usize,
+    ) -> Result<Option<Status<W>>, VmError> {
+        debug_assert_eq!(parameters.len(), self.number_of_parameters());
+        let head = program_state.head;
         match self {
             Op::Add => {
-                program_state.program[parameters[2] as usize] = parameters[0] + parameters[1];
+                let sum = parameters[0]
+                    .checked_add(&parameters[1])
+                    .ok_or(VmError::Overflow { head })?;
+                program_state.write(&parameters[2], sum)?;
             }
             Op::Mult => {
-                program_state.program[parameters[2] as usize] = parameters[0] * parameters[1];
+                let product = parameters[0]
+                    .checked_mul(&parameters[1])
+                    .ok_or(VmError::Overflow { head })?;
+                program_state.write(&parameters[2], product)?;
             }
             Op::Halt => {
                 program_state.running = false;
+                return Ok(Some(Status::Halted));
             }
             Op::Save => {
-                program_state.program[parameters[0] as usize] = program_state.inputs.pop().unwrap();
+                // Leave the instruction in place so it can be retried once
+                // the caller has provided more input.
+                let Some(input) = program_state.next_input() else {
+                    return Ok(Some(Status::NeedsInput));
+                };
+                program_state.write(&parameters[0], input)?;
             }
             Op::Read => {
-                program_state.outputs.push(parameters[0]);
+                program_state.emit_output(parameters[0].clone());
+                return Ok(Some(Status::Output(parameters[0].clone())));
             }
             Op::JumpIfTrue => {
-                if parameters[0] != 0 {
-                    program_state.head = parameters[1] as usize;
+                if !parameters[0].is_zero() {
+                    *next_head = program_state.resolve_address(&parameters[1])?;
                 }
             }
             Op::JumpIfFalse => {
-                if parameters[0] == 0 {
-                    program_state.head = parameters[1] as usize;
+                if parameters[0].is_zero() {
+                    *next_head = program_state.resolve_address(&parameters[1])?;
                 }
             }
             Op::LessThan => {
-                program_state.program[parameters[2] as usize] =
-                    i32::from(parameters[0] < parameters[1]);
+                let less_than = W::from_i64(i64::from(parameters[0] < parameters[1]))
+                    .ok_or(VmError::Overflow { head })?;
+                program_state.write(&parameters[2], less_than)?;
             }
             Op::Equals => {
-                program_state.program[parameters[2] as usize] =
-                    i32::from(parameters[0] == parameters[1]);
+                let equals = W::from_i64(i64::from(parameters[0] == parameters[1]))
+                    .ok_or(VmError::Overflow { head })?;
+                program_state.write(&parameters[2], equals)?;
+            }
+            Op::AdjustRelativeBase => {
+                program_state.relative_base = program_state
+                    .relative_base
+                    .checked_add(&parameters[0])
+                    .ok_or(VmError::Overflow { head })?;
             }
         }
-        true
-    }
-}
-#[derive(Debug)]
-enum ParamType {
-    Position,
-    Immediate,
-}
-impl ParamType {
-    fn from_digit(digit: i32) -> Self {
-        match digit {
-            0 => ParamType::Position,
-            1 => ParamType::Immediate,
-            _ => todo!(),
-        }
+        Ok(None)
     }
 }
-#[derive(Debug)]
-pub struct OpCode {
-    op: Op,
-    param_modes: [ParamType; 3],
-}
 impl OpCode {
-    pub fn parse(code: &i32) -> Self {
-        let digits: Vec<i32> = format!("{:0>5}", code.to_string())
-            .chars()
-            .map(|x| x.to_digit(10).unwrap() as i32)
-            .collect();
-        OpCode {
-            op: Op::from_digits(digits[3] * 10 + digits[4]),
-            param_modes: [
-                ParamType::from_digit(digits[2]),
-                ParamType::from_digit(digits[1]),
-                ParamType::from_digit(digits[0]),
-            ],
-        }
+    // `head` is the address the instruction word was fetched from and is
+    // only used to report decoding errors.
+    pub fn parse<W: Word>(code: &W, head: usize) -> Result<Self, VmError> {
+        let unknown_opcode = || VmError::UnknownOpcode {
+            head,
+            code: code.to_string(),
+        };
+        let word = code.to_i64().ok_or_else(unknown_opcode)?;
+        OpCode::decode(word).map_err(|error| match error {
+            DecodeError::UnknownOpcode => unknown_opcode(),
+            DecodeError::BadParameterMode { parameter, mode } => VmError::BadParameterMode {
+                head,
+                parameter,
+                mode,
+            },
+        })
+    }
+    // Executes the instruction at `program_state.head`. `next_head` starts out
+    // pointing just past the instruction and is overwritten by taken jumps.
+    pub fn execute<W: Word>(
+        &self,
+        program_state: &mut ProgramState<W>,
+        next_head: &mut usize,
+    ) -> Result<Option<Status<W>>, VmError> {
+        let mut parameters: [W; 3] = Default::default();
+        self.resolve_parameters_into(program_state, &mut parameters)?;
+        let parameters = &parameters[..self.op().number_of_parameters()];
+        self.op().execute(program_state, parameters, next_head)
     }
-    pub fn get_instruction_size(&self) -> usize {
-        1 + self.op.number_of_parameters()
+    // The values the instruction at `program_state.head` operates on. A
+    // parameter that is written to resolves to the address written.
+    pub fn resolve_parameters<W: Word>(
+        &self,
+        program_state: &ProgramState<W>,
+    ) -> Result<Vec<W>, VmError> {
+        let mut parameters: [W; 3] = Default::default();
+        self.resolve_parameters_into(program_state, &mut parameters)?;
+        Ok(parameters[..self.op().number_of_parameters()].to_vec())
     }
-    pub fn execute(&self, program_state: &mut ProgramState) -> bool {
-        let mut parameters: Vec<i32> = Vec::new();
-        if self.op.number_of_parameters() > 0 {
-            for parameter_index in 0..self.op.number_of_parameters() {
-                let parameter = program_state.program[program_state.head + parameter_index + 1];
-                match self.param_modes[parameter_index] {
-                    ParamType::Position => {
-                        parameters.push(program_state.program[parameter as usize])
-                    }
-                    ParamType::Immediate => parameters.push(parameter),
+    // Like `resolve_parameters`, but fills the front of a fixed array so the
+    // hot path does not allocate.
+    pub fn resolve_parameters_into<W: Word>(
+        &self,
+        program_state: &ProgramState<W>,
+        parameters: &mut [W; 3],
+    ) -> Result<(), VmError> {
+        let head = program_state.head;
+        let mut reads = self.op().number_of_parameters();
+        // parameters an instruction writes to are never in immediate mode!
+        // They resolve to an address rather than to the value stored there.
+        if self.op().writes_to_program() {
+            reads -= 1;
+            let target = &program_state.program[head + reads + 1];
+            parameters[reads] = match self.param_modes()[reads] {
+                ParamType::Position => target.clone(),
+                ParamType::Immediate => return Err(VmError::ImmediateWrite { head }),
+                ParamType::Relative => relative_address(program_state, target)?,
+            };
+        }
+        for (parameter_index, value) in parameters.iter_mut().enumerate().take(reads) {
+            let parameter = &program_state.program[head + parameter_index + 1];
+            *value = match self.param_modes()[parameter_index] {
+                ParamType::Position => program_state.read(parameter)?,
+                ParamType::Immediate => parameter.clone(),
+                ParamType::Relative => {
+                    program_state.read(&relative_address(program_state, parameter)?)?
                 }
-            }
-            // parameters an instruction writes to are never in immediate mode!
-            if self.op.writes_to_program() {
-                parameters[self.op.number_of_parameters() - 1] =
-                    program_state.program[program_state.head + self.op.number_of_parameters()];
+            };
+        }
+        Ok(())
+    }
+    // The memory cells the instruction at `program_state.head` reads its
+    // parameters from. Immediates and the parameter written to read nothing.
+    pub fn read_addresses<W: Word>(
+        &self,
+        program_state: &ProgramState<W>,
+    ) -> Result<Vec<usize>, VmError> {
+        let mut reads_from = self.op().number_of_parameters();
+        if self.op().writes_to_program() {
+            reads_from -= 1;
+        }
+        let mut addresses = Vec::new();
+        for parameter_index in 0..reads_from {
+            let parameter = &program_state.program[program_state.head + parameter_index + 1];
+            match self.param_modes()[parameter_index] {
+                ParamType::Position => addresses.push(program_state.resolve_address(parameter)?),
+                ParamType::Immediate => {}
+                ParamType::Relative => addresses.push(
+                    program_state.resolve_address(&relative_address(program_state, parameter)?)?,
+                ),
             }
         }
-        self.op.execute(program_state, &parameters)
+        Ok(addresses)
     }
 }
+fn relative_address<W: Word>(program_state: &ProgramState<W>, offset: &W) -> Result<W, VmError> {
+    program_state
+        .relative_base
+        .checked_add(offset)
+        .ok_or(VmError::Overflow {
+            head: program_state.head,
+        })
+}
@@ -0,0 +1,283 @@
+// Instruction decoding on its own. The build script compiles this file along
+// with the translator, so it uses nothing but std; `opcode` adds running
+// instructions on top.
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Op {
+    Add,
+    Mult,
+    Save,
+    Read,
+    JumpIfTrue,
+    JumpIfFalse,
+    LessThan,
+    Equals,
+    AdjustRelativeBase,
+    Halt,
+}
+impl Op {
+    fn from_digits(code: i32) -> Option<Self> {
+        match code {
+            1 => Some(Op::Add),
+            2 => Some(Op::Mult),
+            3 => Some(Op::Save),
+            4 => Some(Op::Read),
+            5 => Some(Op::JumpIfTrue),
+            6 => Some(Op::JumpIfFalse),
+            7 => Some(Op::LessThan),
+            8 => Some(Op::Equals),
+            9 => Some(Op::AdjustRelativeBase),
+            99 => Some(Op::Halt),
+            _ => None,
+        }
+    }
+    pub fn code(&self) -> i64 {
+        match self {
+            Op::Add => 1,
+            Op::Mult => 2,
+            Op::Save => 3,
+            Op::Read => 4,
+            Op::JumpIfTrue => 5,
+            Op::JumpIfFalse => 6,
+            Op::LessThan => 7,
+            Op::Equals => 8,
+            Op::AdjustRelativeBase => 9,
+            Op::Halt => 99,
+        }
+    }
+    pub fn from_mnemonic(mnemonic: &str) -> Option<Self> {
+        [
+            Op::Add,
+            Op::Mult,
+            Op::Save,
+            Op::Read,
+            Op::JumpIfTrue,
+            Op::JumpIfFalse,
+            Op::LessThan,
+            Op::Equals,
+            Op::AdjustRelativeBase,
+            Op::Halt,
+        ]
+        .into_iter()
+        .find(|op| op.mnemonic().eq_ignore_ascii_case(mnemonic))
+    }
+    pub fn mnemonic(&self) -> &'static str {
+        match self {
+            Op::Add => "ADD",
+            Op::Mult => "MUL",
+            Op::Save => "IN",
+            Op::Read => "OUT",
+            Op::JumpIfTrue => "JT",
+            Op::JumpIfFalse => "JF",
+            Op::LessThan => "LT",
+            Op::Equals => "EQ",
+            Op::AdjustRelativeBase => "ARB",
+            Op::Halt => "HLT",
+        }
+    }
+    // The last parameter of these ops is the address they write to.
+    pub fn writes_to_program(&self) -> bool {
+        matches!(
+            self,
+            Op::Add | Op::Mult | Op::Save | Op::LessThan | Op::Equals
+        )
+    }
+    pub fn is_jump(&self) -> bool {
+        matches!(self, Op::JumpIfTrue | Op::JumpIfFalse)
+    }
+    pub fn number_of_parameters(&self) -> usize {
+        match self {
+            Op::Add => 3,
+            Op::Mult => 3,
+            Op::Save => 1,
+            Op::Read => 1,
+            Op::Halt => 0,
+            Op::JumpIfTrue => 2,
+            Op::JumpIfFalse => 2,
+            Op::LessThan => 3,
+            Op::Equals => 3,
+            Op::AdjustRelativeBase => 1,
+        }
+    }
+}
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ParamType {
+    Position,
+    Immediate,
+    Relative,
+}
+impl ParamType {
+    pub fn digit(&self) -> i64 {
+        match self {
+            ParamType::Position => 0,
+            ParamType::Immediate => 1,
+            ParamType::Relative => 2,
+        }
+    }
+    fn from_digit(digit: i32) -> Option<Self> {
+        match digit {
+            0 => Some(ParamType::Position),
+            1 => Some(ParamType::Immediate),
+            2 => Some(ParamType::Relative),
+            _ => None,
+        }
+    }
+}
+
+// Why a word is not an instruction. `OpCode::parse` turns these into
+// `VmError`s carrying the address.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DecodeError {
+    UnknownOpcode,
+    BadParameterMode { parameter: usize, mode: i32 },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EdgeKind {
+    // Execution carries on with the next instruction.
+    Fallthrough,
+    Jump,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OpCode {
+    op: Op,
+    param_modes: [ParamType; 3],
+}
+impl OpCode {
+    // The instruction `op` with the given modes for its parameters, in
+    // order; any it does not take are position mode.
+    pub fn new(op: Op, modes: &[ParamType]) -> Self {
+        let mut param_modes = [ParamType::Position; 3];
+        param_modes[..modes.len()].copy_from_slice(modes);
+        OpCode { op, param_modes }
+    }
+    pub fn decode(code: i64) -> Result<Self, DecodeError> {
+        let code = match code {
+            0..=99999 => code as i32,
+            _ => return Err(DecodeError::UnknownOpcode),
+        };
+        let op = Op::from_digits(code % 100).ok_or(DecodeError::UnknownOpcode)?;
+        let mut param_modes = [
+            ParamType::Position,
+            ParamType::Position,
+            ParamType::Position,
+        ];
+        // The hundreds digit is the first parameter's mode, the thousands
+        // digit the second's and so on.
+        let mut modes = code / 100;
+        for (parameter_index, param_mode) in param_modes.iter_mut().enumerate() {
+            let digit = modes % 10;
+            modes /= 10;
+            *param_mode = ParamType::from_digit(digit).ok_or(DecodeError::BadParameterMode {
+                parameter: parameter_index + 1,
+                mode: digit,
+            })?;
+        }
+        Ok(OpCode { op, param_modes })
+    }
+    pub fn op(&self) -> Op {
+        self.op
+    }
+    // The word the assembler writes for this instruction. `decode` also
+    // accepts words with mode digits for parameters the op does not take,
+    // which have no other spelling.
+    pub fn code(&self) -> i64 {
+        let mut code = self.op.code();
+        let mut place = 100;
+        for mode in self.param_modes() {
+            code += mode.digit() * place;
+            place *= 10;
+        }
+        code
+    }
+    // Only the modes of parameters the op actually takes.
+    pub fn param_modes(&self) -> &[ParamType] {
+        &self.param_modes[..self.op.number_of_parameters()]
+    }
+    pub fn get_instruction_size(&self) -> usize {
+        1 + self.op.number_of_parameters()
+    }
+    // Decodes, but always fails when run.
+    pub fn writes_to_immediate(&self) -> bool {
+        self.op.writes_to_program() && self.param_modes().last() == Some(&ParamType::Immediate)
+    }
+    // Where execution can go after this instruction at `address`, given its
+    // operand words. Jumps only have a known target in immediate mode, and a
+    // jump whose condition is an immediate always or never goes. Also returns
+    // whether it may jump to a target read from memory.
+    pub fn successors<T: Default + PartialEq>(
+        &self,
+        address: usize,
+        operands: &[T],
+        to_address: impl Fn(&T) -> Option<usize>,
+    ) -> (Vec<(usize, EdgeKind)>, bool) {
+        let next = (address + self.get_instruction_size(), EdgeKind::Fallthrough);
+        let jumps_if_zero = match self.op {
+            Op::Halt => return (Vec::new(), false),
+            Op::JumpIfTrue => false,
+            Op::JumpIfFalse => true,
+            _ => return (vec![next], false),
+        };
+        let condition = match self.param_modes[0] {
+            ParamType::Immediate => Some((operands[0] == T::default()) == jumps_if_zero),
+            _ => None,
+        };
+        let target = match self.param_modes[1] {
+            ParamType::Immediate => to_address(&operands[1]),
+            _ => None,
+        };
+        let mut edges = Vec::new();
+        if condition != Some(false) {
+            if let Some(target) = target {
+                edges.push((target, EdgeKind::Jump));
+            }
+        }
+        if condition != Some(true) {
+            edges.push(next);
+        }
+        (edges, condition != Some(false) && target.is_none())
+    }
+    // The instruction as the disassembler lists it, such as
+    // `ADD [12], #-1, rb+3`.
+    pub fn format<T: fmt::Display + Default + PartialOrd>(&self, operands: &[T]) -> String {
+        let mut line = self.op.mnemonic().to_string();
+        for (index, (mode, value)) in self.param_modes().iter().zip(operands).enumerate() {
+            line.push_str(if index == 0 { " " } else { ", " });
+            line.push_str(&format_operand(*mode, value));
+        }
+        line
+    }
+}
+
+// `[12]` reads address 12, `#5` is the value 5 and `rb+3` is three past the
+// relative base.
+pub fn format_operand<T: fmt::Display + Default + PartialOrd>(
+    mode: ParamType,
+    value: &T,
+) -> String {
+    match mode {
+        ParamType::Position => format!("[{}]", value),
+        ParamType::Immediate => format!("#{}", value),
+        ParamType::Relative if *value < T::default() => format!("rb{}", value),
+        ParamType::Relative => format!("rb+{}", value),
+    }
+}
+
+// The instruction starting at `address`, if the word there is a valid opcode
+// whose parameters fit in the image and which never writes to an immediate.
+pub fn decode_at<T>(
+    image: &[T],
+    address: usize,
+    to_i64: impl Fn(&T) -> Option<i64>,
+) -> Option<OpCode> {
+    let opcode = OpCode::decode(to_i64(image.get(address)?)?).ok()?;
+    if address + opcode.get_instruction_size() > image.len() {
+        return None;
+    }
+    if opcode.writes_to_immediate() {
+        return None;
+    }
+    Some(opcode)
+}
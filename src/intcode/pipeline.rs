@@ -0,0 +1,324 @@
+use crate::intcode::error::VmError;
+use crate::intcode::io::ChannelIo;
+use crate::intcode::program::ProgramState;
+use crate::intcode::word::Word;
+use std::fmt;
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::thread::{self, JoinHandle};
+
+// A machine's own thread hands it back once it halts.
+pub type MachineHandle<W> = JoinHandle<Result<ProgramState<W>, VmError>>;
+
+// Runs a machine on its own thread, reading from `inputs` and sending each
+// output to every one of `outputs`. Inputs queued on the machine itself are
+// not read; send them on the channel first. The machine's channels are closed
+// when it stops, so a machine waiting on it sees starvation rather than
+// blocking forever.
+pub fn spawn<W: Word>(
+    program_state: ProgramState<W>,
+    inputs: Receiver<W>,
+    outputs: Vec<Sender<W>>,
+) -> MachineHandle<W> {
+    spawn_with(program_state, inputs, outputs, interpret)
+}
+// `spawn`, running the machine with `runner` rather than `ProgramState::run`,
+// e.g. through an `Engine` or a translated program.
+pub fn spawn_with<W: Word, R>(
+    mut program_state: ProgramState<W>,
+    inputs: Receiver<W>,
+    outputs: Vec<Sender<W>>,
+    runner: R,
+) -> MachineHandle<W>
+where
+    R: FnOnce(ProgramState<W>) -> (ProgramState<W>, Result<(), VmError>) + Send + 'static,
+{
+    program_state.set_io(ChannelIo::broadcast(inputs, outputs));
+    thread::spawn(move || {
+        let (mut program_state, result) = runner(program_state);
+        program_state.io = None;
+        result.map(|_| program_state)
+    })
+}
+// Runs a machine until it halts or fails, handing it back either way.
+fn interpret<W: Word>(
+    mut program_state: ProgramState<W>,
+) -> (ProgramState<W>, Result<(), VmError>) {
+    let result = program_state.run();
+    (program_state, result)
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PipelineError {
+    pub machine: usize,
+    pub error: VmError,
+}
+impl fmt::Display for PipelineError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "machine {} failed: {}", self.machine, self.error)
+    }
+}
+impl std::error::Error for PipelineError {}
+
+// How machines are wired together: where each one's outputs go, and which
+// machines' outputs are the pipeline's.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Topology {
+    pub links: Vec<Vec<usize>>,
+    pub sinks: Vec<usize>,
+}
+impl Topology {
+    // Each machine feeds the next; the last one's outputs are the result.
+    pub fn chain(length: usize) -> Self {
+        Topology {
+            links: (0..length)
+                .map(|index| match index + 1 < length {
+                    true => vec![index + 1],
+                    false => Vec::new(),
+                })
+                .collect(),
+            sinks: length.checked_sub(1).into_iter().collect(),
+        }
+    }
+    // A chain whose last machine also feeds the first. The last machine's
+    // outputs are the result, including any the first never reads. Every
+    // machine in a ring has another feeding it, so none starves: one with
+    // no inputs queued must be sent some through `Pipeline::input` before
+    // `join`, or `join` waits forever on machines all waiting to read.
+    pub fn ring(length: usize) -> Self {
+        Topology {
+            links: (0..length)
+                .map(|index| vec![(index + 1) % length])
+                .collect(),
+            sinks: length.checked_sub(1).into_iter().collect(),
+        }
+    }
+    // Machine 0 feeds each of `consumers` others, whose outputs are the
+    // result, interleaved in whatever order they arrive.
+    pub fn fan_out(consumers: usize) -> Self {
+        let mut links = vec![(1..=consumers).collect::<Vec<_>>()];
+        links.extend((0..consumers).map(|_| Vec::new()));
+        Topology {
+            links,
+            sinks: (1..=consumers).collect(),
+        }
+    }
+    pub fn len(&self) -> usize {
+        self.links.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.links.is_empty()
+    }
+    // Starts one thread per machine, wired up as described. Inputs queued
+    // on a machine are what it reads first.
+    pub fn spawn<W: Word>(&self, machines: Vec<ProgramState<W>>) -> Pipeline<W> {
+        self.spawn_with(machines, interpret)
+    }
+    // `spawn`, running each machine with `runner`.
+    pub fn spawn_with<W: Word, R>(&self, machines: Vec<ProgramState<W>>, runner: R) -> Pipeline<W>
+    where
+        R: Fn(ProgramState<W>) -> (ProgramState<W>, Result<(), VmError>) + Clone + Send + 'static,
+    {
+        assert_eq!(
+            machines.len(),
+            self.len(),
+            "Should have been given one machine per node"
+        );
+        let (senders, receivers): (Vec<Sender<W>>, Vec<Receiver<W>>) =
+            (0..self.len()).map(|_| channel()).unzip();
+        let (output_sender, outputs) = channel();
+        let handles = machines
+            .into_iter()
+            .zip(receivers)
+            .enumerate()
+            .map(|(index, (mut machine, receiver))| {
+                for value in machine.inputs.drain(..) {
+                    let _ = senders[index].send(value);
+                }
+                let mut targets: Vec<Sender<W>> = self.links[index]
+                    .iter()
+                    .map(|&target| senders[target].clone())
+                    .collect();
+                if self.sinks.contains(&index) {
+                    targets.push(output_sender.clone());
+                }
+                spawn_with(machine, receiver, targets, runner.clone())
+            })
+            .collect();
+        Pipeline {
+            input: senders.into_iter().next(),
+            handles,
+            outputs,
+        }
+    }
+}
+
+// Machines running on their own threads.
+#[derive(Debug)]
+pub struct Pipeline<W: Word = i64> {
+    // Feeds machine 0 alongside anything linked to it. Dropped by `join`, so
+    // a machine 0 nothing else feeds sees starvation once it is done.
+    pub input: Option<Sender<W>>,
+    pub handles: Vec<MachineHandle<W>>,
+    // Outputs of the topology's sinks, as they are produced.
+    pub outputs: Receiver<W>,
+}
+impl<W: Word> Pipeline<W> {
+    // Waits for every machine and returns the pipeline's outputs, or the
+    // first failure by machine index. Never returns if the machines are all
+    // waiting on each other, as in a ring with no input.
+    pub fn join(mut self) -> Result<Vec<W>, PipelineError> {
+        self.input = None;
+        let mut failure = None;
+        for (machine, handle) in self.handles.into_iter().enumerate() {
+            let result = handle
+                .join()
+                .unwrap_or_else(|panic| std::panic::resume_unwind(panic));
+            if let (Err(error), None) = (result, &failure) {
+                failure = Some(PipelineError { machine, error });
+            }
+        }
+        match failure {
+            Some(error) => Err(error),
+            None => Ok(self.outputs.try_iter().collect()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::intcode::asm::assemble;
+    use crate::intcode::engine::Engine;
+    use crate::intcode::error::VmError;
+    use crate::intcode::loader::ProgramBuilder;
+    use crate::intcode::pipeline::{PipelineError, Topology};
+    use crate::intcode::program::ProgramState;
+    use std::thread;
+    use std::time::Duration;
+
+    // Reads a value and sends it on plus `step`, until its input runs dry.
+    fn adder(step: i64) -> ProgramState {
+        let image = assemble(&format!(
+            "
+            loop:   IN [x]
+                    ADD [x], #{step}, [x]
+                    OUT [x]
+                    JT #1, #loop
+            x:      data 0"
+        ))
+        .unwrap();
+        ProgramBuilder::new(image).build()
+    }
+
+    // Reads a value, sends it on plus `step` and halts.
+    fn once(step: i64) -> ProgramState {
+        let image = assemble(&format!(
+            "
+                    IN [x]
+                    ADD [x], #{step}, [x]
+                    OUT [x]
+                    HLT
+            x:      data 0"
+        ))
+        .unwrap();
+        ProgramBuilder::new(image).build()
+    }
+
+    #[test]
+    fn test_topologies() {
+        assert_eq!(Topology::chain(3).links, vec![vec![1], vec![2], vec![]]);
+        assert_eq!(Topology::chain(3).sinks, vec![2]);
+        assert_eq!(Topology::ring(3).links, vec![vec![1], vec![2], vec![0]]);
+        assert_eq!(Topology::ring(3).sinks, vec![2]);
+        assert_eq!(Topology::fan_out(2).links, vec![vec![1, 2], vec![], vec![]]);
+        assert_eq!(Topology::fan_out(2).sinks, vec![1, 2]);
+        assert!(Topology::chain(0).is_empty());
+    }
+    #[test]
+    fn test_chain() {
+        let mut first = once(1);
+        first.push_input(10);
+        let pipeline = Topology::chain(3).spawn(vec![first, once(10), once(100)]);
+        assert_eq!(pipeline.join(), Ok(vec![121]));
+    }
+    #[test]
+    fn test_spawn_with() {
+        let mut first = once(1);
+        first.push_input(10);
+        let pipeline = Topology::chain(2).spawn_with(vec![first, once(10)], |machine| {
+            let mut engine = Engine::new(machine);
+            let result = engine.run();
+            (engine.into_inner(), result)
+        });
+        assert_eq!(pipeline.join(), Ok(vec![21]));
+    }
+    #[test]
+    fn test_chain_input() {
+        let pipeline = Topology::chain(2).spawn(vec![once(1), once(2)]);
+        pipeline.input.as_ref().unwrap().send(5).unwrap();
+        assert_eq!(pipeline.join(), Ok(vec![8]));
+    }
+    #[test]
+    fn test_ring() {
+        // Whichever machine halts first starves the rest in turn.
+        let mut first = adder(1);
+        first.push_input(0);
+        let pipeline = Topology::ring(3).spawn(vec![first, adder(1), once(1)]);
+        assert_eq!(
+            pipeline.join(),
+            Err(PipelineError {
+                machine: 0,
+                error: VmError::InputUnderflow { head: 0 }
+            })
+        );
+        let mut first = once(1);
+        first.push_input(0);
+        let pipeline = Topology::ring(3).spawn(vec![first, adder(1), adder(1)]);
+        assert_eq!(
+            pipeline.join(),
+            Err(PipelineError {
+                machine: 1,
+                error: VmError::InputUnderflow { head: 0 }
+            })
+        );
+    }
+    #[test]
+    fn test_ring_without_input_waits() {
+        let pipeline = Topology::ring(2).spawn(vec![once(1), once(1)]);
+        thread::sleep(Duration::from_millis(100));
+        assert!(pipeline.handles.iter().all(|handle| !handle.is_finished()));
+        pipeline.input.as_ref().unwrap().send(0).unwrap();
+        assert_eq!(pipeline.join(), Ok(vec![2]));
+    }
+    #[test]
+    fn test_fan_out() {
+        let mut first = once(0);
+        first.push_input(10);
+        let pipeline = Topology::fan_out(3).spawn(vec![first, once(1), once(2), once(3)]);
+        let mut outputs = pipeline.join().unwrap();
+        outputs.sort();
+        assert_eq!(outputs, vec![11, 12, 13]);
+    }
+    #[test]
+    fn test_failure() {
+        // Machine 1 wants two values but machine 0 only ever sends one.
+        let image = assemble(
+            "
+                    IN [x]
+                    IN [x]
+                    OUT [x]
+                    HLT
+            x:      data 0",
+        )
+        .unwrap();
+        let mut first = once(0);
+        first.push_input(1);
+        let pipeline = Topology::chain(2).spawn(vec![first, ProgramBuilder::new(image).build()]);
+        assert_eq!(
+            pipeline.join(),
+            Err(PipelineError {
+                machine: 1,
+                error: VmError::InputUnderflow { head: 2 }
+            })
+        );
+    }
+}
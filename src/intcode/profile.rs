@@ -0,0 +1,291 @@
+use crate::intcode::io::StdIo;
+use crate::intcode::loader::Intcode;
+use crate::intcode::opcode::Op;
+use crate::intcode::trace::{TraceEvent, TraceSink};
+use crate::intcode::word::Word;
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::hash::Hash;
+use std::io::{self, Write};
+use std::sync::{Arc, Mutex};
+
+// Rows shown in each table of the report.
+const REPORT_ROWS: usize = 10;
+
+// Execution counts gathered from a trace. Attach one with
+// `ProgramBuilder::trace(Arc<Mutex<Profile>>)` and read it back afterwards.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Profile {
+    pub cycles: u64,
+    pub by_address: HashMap<usize, u64>,
+    pub by_op: HashMap<Op, u64>,
+    pub reads: HashMap<usize, u64>,
+    pub writes: HashMap<usize, u64>,
+    // Taken jumps back to an earlier address, keyed by (target, jump
+    // address): each one is an iteration of the loop spanning them.
+    pub loops: HashMap<(usize, usize), u64>,
+    // Instructions executed under each call stack, see `record`.
+    pub stacks: HashMap<StackId, u64>,
+    // Every call stack seen, interned as a tree: stack 0 is `main` and each
+    // other is its parent plus the function entered.
+    frames: Vec<Frame>,
+    callees: HashMap<(StackId, usize), StackId>,
+    stack: StackId,
+    last_jump_target: usize,
+}
+pub type StackId = usize;
+#[derive(Debug, Clone, PartialEq)]
+struct Frame {
+    parent: StackId,
+    entry: usize,
+}
+impl Default for Profile {
+    fn default() -> Self {
+        Profile {
+            cycles: 0,
+            by_address: HashMap::new(),
+            by_op: HashMap::new(),
+            reads: HashMap::new(),
+            writes: HashMap::new(),
+            loops: HashMap::new(),
+            stacks: HashMap::new(),
+            frames: vec![Frame {
+                parent: 0,
+                entry: 0,
+            }],
+            callees: HashMap::new(),
+            stack: 0,
+            last_jump_target: 0,
+        }
+    }
+}
+impl Profile {
+    pub fn new() -> Self {
+        Profile::default()
+    }
+    // The entry addresses of the functions on `stack`, outermost first.
+    pub fn frames(&self, mut stack: StackId) -> Vec<usize> {
+        let mut entries = Vec::new();
+        while stack != 0 {
+            entries.push(self.frames[stack].entry);
+            stack = self.frames[stack].parent;
+        }
+        entries.reverse();
+        entries
+    }
+    fn call(&mut self, entry: usize) {
+        let next = self.frames.len();
+        let parent = self.stack;
+        self.stack = *self.callees.entry((parent, entry)).or_insert(next);
+        if self.stack == next {
+            self.frames.push(Frame { parent, entry });
+        }
+    }
+    fn ret(&mut self) {
+        self.stack = self.frames[self.stack].parent;
+    }
+    // Writes the call stacks in the folded format flamegraph tools read:
+    // `main;fn@12;fn@40 1234`, one stack per line.
+    pub fn write_folded<O: Write>(&self, mut out: O) -> io::Result<()> {
+        let mut stacks: Vec<(String, u64)> = self
+            .stacks
+            .iter()
+            .map(|(stack, count)| {
+                let frames: Vec<String> = std::iter::once("main".to_string())
+                    .chain(
+                        self.frames(*stack)
+                            .iter()
+                            .map(|entry| format!("fn@{}", entry)),
+                    )
+                    .collect();
+                (frames.join(";"), *count)
+            })
+            .collect();
+        stacks.sort();
+        for (stack, count) in stacks {
+            writeln!(out, "{} {}", stack, count)?;
+        }
+        Ok(())
+    }
+}
+impl<W: Word> TraceSink<W> for Profile {
+    // Intcode has no call instruction, so stacks are inferred from the
+    // relative base: compiled programs grow it to open a stack frame on
+    // entering a function and shrink it again before returning. The
+    // function is named after the last jump target before the frame opened.
+    fn record(&mut self, event: &TraceEvent<W>) {
+        self.cycles += 1;
+        *self.by_address.entry(event.address).or_default() += 1;
+        *self.by_op.entry(event.opcode.op()).or_default() += 1;
+        for address in &event.reads {
+            *self.reads.entry(*address).or_default() += 1;
+        }
+        if let Some((address, _)) = &event.write {
+            *self.writes.entry(*address).or_default() += 1;
+        }
+        *self.stacks.entry(self.stack).or_default() += 1;
+        let parameters = &event.parameters;
+        let taken = match event.opcode.op() {
+            Op::JumpIfTrue => !parameters[0].is_zero(),
+            Op::JumpIfFalse => parameters[0].is_zero(),
+            Op::AdjustRelativeBase => {
+                if parameters[0] > W::default() {
+                    self.call(self.last_jump_target);
+                } else if !parameters[0].is_zero() {
+                    self.ret();
+                }
+                false
+            }
+            _ => false,
+        };
+        if let Some(target) = parameters.get(1).and_then(|target| target.to_address()) {
+            if taken {
+                self.last_jump_target = target;
+                if target <= event.address {
+                    *self.loops.entry((target, event.address)).or_default() += 1;
+                }
+            }
+        }
+    }
+}
+
+fn top<K: Copy + Hash + Ord>(counts: &HashMap<K, u64>) -> Vec<(K, u64)> {
+    let mut rows: Vec<(K, u64)> = counts.iter().map(|(key, count)| (*key, *count)).collect();
+    rows.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+    rows.truncate(REPORT_ROWS);
+    rows
+}
+fn percent(count: u64, total: u64) -> f64 {
+    100.0 * count as f64 / total.max(1) as f64
+}
+impl fmt::Display for Profile {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "cycles: {}", self.cycles)?;
+        writeln!(f, "\nby op:")?;
+        let mut ops: Vec<(Op, u64)> = self.by_op.iter().map(|(op, n)| (*op, *n)).collect();
+        ops.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.code().cmp(&b.0.code())));
+        for (op, count) in ops {
+            let share = percent(count, self.cycles);
+            writeln!(f, "  {:<4} {:>12} {:>6.2}%", op.mnemonic(), count, share)?;
+        }
+        writeln!(f, "\nhottest addresses:")?;
+        for (address, count) in top(&self.by_address) {
+            let share = percent(count, self.cycles);
+            writeln!(f, "  {:>6} {:>12} {:>6.2}%", address, count, share)?;
+        }
+        writeln!(f, "\nhottest loops:")?;
+        for ((start, end), iterations) in top(&self.loops) {
+            writeln!(
+                f,
+                "  {:>6}..={:<6} {:>12} iterations",
+                start, end, iterations
+            )?;
+        }
+        writeln!(f, "\nbusiest memory:")?;
+        let mut traffic = self.reads.clone();
+        for (address, count) in &self.writes {
+            *traffic.entry(*address).or_default() += count;
+        }
+        for (address, _) in top(&traffic) {
+            let reads = self.reads.get(&address).copied().unwrap_or(0);
+            let writes = self.writes.get(&address).copied().unwrap_or(0);
+            writeln!(
+                f,
+                "  {:>6} {:>12} reads {:>12} writes",
+                address, reads, writes
+            )?;
+        }
+        Ok(())
+    }
+}
+
+// Runs a program against the terminal, then prints a profile to stderr and
+// writes the folded stacks next to the program.
+pub fn profile(file_path: String) {
+    let program: Intcode =
+        Intcode::from_file(&file_path).expect("Should have been able to load the program");
+    let profile = Arc::new(Mutex::new(Profile::new()));
+    let mut program_state = program.builder().io(StdIo).trace(profile.clone()).build();
+    let result = program_state.run();
+    let profile = profile.lock().unwrap();
+    eprintln!("{}", profile);
+    let folded_path = format!("{}.folded", file_path);
+    let folded = fs::File::create(&folded_path).expect("Should have been able to create the file");
+    profile
+        .write_folded(io::BufWriter::new(folded))
+        .expect("Should have been able to write the folded stacks");
+    eprintln!("folded stacks written to {}", folded_path);
+    result.expect("Should have been able to run the program");
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::intcode::asm::{assemble, COUNTDOWN};
+    use crate::intcode::loader::ProgramBuilder;
+    use crate::intcode::opcode::Op;
+    use crate::intcode::profile::Profile;
+    use std::sync::{Arc, Mutex};
+
+    fn profiled(source: &str, inputs: Vec<i64>) -> Profile {
+        let profile = Arc::new(Mutex::new(Profile::new()));
+        ProgramBuilder::new(assemble(source).unwrap())
+            .inputs(inputs)
+            .trace(profile.clone())
+            .build()
+            .run()
+            .unwrap();
+        let profile = profile.lock().unwrap().clone();
+        profile
+    }
+    #[test]
+    fn test_counts() {
+        let profile = profiled(COUNTDOWN, vec![4]);
+        assert_eq!(profile.cycles, 1 + 4 * 3 + 1);
+        assert_eq!(profile.by_address[&2], 4);
+        assert_eq!(profile.by_op[&Op::Add], 4);
+        assert_eq!(profile.by_op[&Op::Halt], 1);
+        // OUT, ADD and JT all read the counter; IN and ADD write it.
+        assert_eq!(profile.reads[&12], 12);
+        assert_eq!(profile.writes[&12], 5);
+        assert_eq!(profile.loops[&(2, 8)], 3);
+        assert_eq!(profile.loops.len(), 1);
+    }
+    #[test]
+    fn test_folded_stacks() {
+        // main calls `double` twice; the callee opens a one-word frame.
+        let profile = profiled(
+            "
+                        ADD #ret1, #0, rb
+                        JT #1, #double
+                ret1:   ADD #ret2, #0, rb
+                        JT #1, #double
+                ret2:   HLT
+                double: ARB #1
+                        MUL [x], #2, [x]
+                        ARB #-1
+                        JT #1, rb
+                x:      data 3",
+            Vec::new(),
+        );
+        let mut folded = Vec::new();
+        profile.write_folded(&mut folded).unwrap();
+        assert_eq!(String::from_utf8(folded).unwrap(), "main 9\nmain;fn@15 4\n");
+        // Both calls count towards the one interned stack.
+        let mut frames: Vec<Vec<usize>> = profile
+            .stacks
+            .keys()
+            .map(|stack| profile.frames(*stack))
+            .collect();
+        frames.sort();
+        assert_eq!(frames, vec![vec![], vec![15]]);
+    }
+    #[test]
+    fn test_report() {
+        let report = profiled(COUNTDOWN, vec![2]).to_string();
+        assert!(report.starts_with("cycles: 8\n"));
+        assert!(report.contains("  ADD             2  25.00%\n"));
+        assert!(report.contains("       2..=8                 1 iterations\n"));
+        assert!(report.contains("      12            6 reads            3 writes\n"));
+    }
+}
@@ -1,23 +1,561 @@
-use crate::intcode::opcode::OpCode;
+use crate::intcode::error::VmError;
+use crate::intcode::io::IntcodeIo;
+use crate::intcode::journal::{Journal, JournalEntry};
+use crate::intcode::memory::Memory;
+use crate::intcode::opcode::{Op, OpCode};
+use crate::intcode::snapshot::Snapshot;
+use crate::intcode::trace::{TraceEvent, TraceSink};
+use crate::intcode::watchdog::Watchdog;
+use crate::intcode::word::Word;
+use std::collections::VecDeque;
+// Why `update` handed control back to the caller.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Status<W: Word = i64> {
+    Halted,
+    // `head` still points at the input instruction; push an input and call
+    // `update` again to resume.
+    NeedsInput,
+    // The value has also been passed to the program's output.
+    Output(W),
+}
+// One instruction as executed by `ProgramState::step`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Step<W: Word = i64> {
+    pub address: usize,
+    pub opcode: OpCode,
+    // None when execution simply carries on with the next instruction. An
+    // input instruction that found no input reports `NeedsInput` and was not
+    // executed.
+    pub status: Option<Status<W>>,
+}
 #[derive(Debug)]
-pub struct ProgramState {
-    pub program: Vec<i32>,
+pub struct ProgramState<W: Word = i64> {
+    pub program: Memory<W>,
     pub head: usize,
+    pub relative_base: W,
     pub running: bool,
-    pub inputs: Vec<i32>,
-    pub outputs: Vec<i32>,
+    // Number of instructions executed so far.
+    pub cycles: u64,
+    // Both queues are first in, first out: inputs are consumed from the
+    // front and outputs are appended to the back.
+    pub inputs: VecDeque<W>,
+    pub outputs: VecDeque<W>,
+    // When set, replaces the queues above as the program's I/O.
+    pub io: Option<Box<dyn IntcodeIo<W> + Send>>,
+    // When set, told about every instruction executed.
+    pub trace: Option<Box<dyn TraceSink<W> + Send>>,
+    // When set, records how to undo each instruction for `step_back`.
+    pub journal: Option<Journal<W>>,
+    // When set, turns a loop without I/O into an error instead of a hang.
+    pub watchdog: Option<Watchdog<W>>,
 }
-impl ProgramState {
-    pub fn update(&mut self) -> bool {
-        while self.running {
-            let current_op = OpCode::parse(&self.program[self.head]);
-            let current_head = self.head;
-            current_op.execute(self);
-            // Only advance if an instruction didn't already modify head
-            if current_head == self.head {
-                self.head += current_op.get_instruction_size();
+impl<W: Word> ProgramState<W> {
+    pub fn new(image: Vec<W>) -> Self {
+        ProgramState {
+            program: image.into(),
+            head: 0,
+            relative_base: W::default(),
+            running: true,
+            cycles: 0,
+            inputs: VecDeque::new(),
+            outputs: VecDeque::new(),
+            io: None,
+            trace: None,
+            journal: None,
+            watchdog: None,
+        }
+    }
+    // Runs until the program halts, produces an output or runs out of input.
+    pub fn update(&mut self) -> Result<Status<W>, VmError> {
+        while let Some(step) = self.step()? {
+            if let Some(status) = step.status {
+                return Ok(status);
+            }
+        }
+        Ok(Status::Halted)
+    }
+    // Executes the instruction at `head`, or returns None if the program has
+    // already halted.
+    pub fn step(&mut self) -> Result<Option<Step<W>>, VmError> {
+        if !self.running {
+            return Ok(None);
+        }
+        let current_op = OpCode::parse(&self.program[self.head], self.head)?;
+        let current_head = self.head;
+        let mut next_head = current_head + current_op.get_instruction_size();
+        let status = if self.recording() {
+            self.execute_recorded(&current_op, &mut next_head)?
+        } else {
+            current_op.execute(self, &mut next_head)?
+        };
+        if status != Some(Status::NeedsInput) {
+            self.cycles += 1;
+            self.head = next_head;
+            if let Some(watchdog) = &mut self.watchdog {
+                let io = matches!(current_op.op(), Op::Save | Op::Read);
+                watchdog.check(
+                    current_head,
+                    io,
+                    self.head,
+                    &self.relative_base,
+                    &self.program,
+                )?;
+            }
+        }
+        Ok(Some(Step {
+            address: current_head,
+            opcode: current_op,
+            status,
+        }))
+    }
+    // Whether anything is attached that needs to see each instruction's
+    // reads and writes, which rules out faster ways of running.
+    pub fn recording(&self) -> bool {
+        self.trace.is_some() || self.journal.is_some() || self.watchdog.is_some()
+    }
+    // The slow path taken while recording, which looks at what the
+    // instruction reads and writes.
+    fn execute_recorded(
+        &mut self,
+        opcode: &OpCode,
+        next_head: &mut usize,
+    ) -> Result<Option<Status<W>>, VmError> {
+        let operands: Vec<W> = (1..opcode.get_instruction_size())
+            .map(|offset| self.program[self.head + offset].clone())
+            .collect();
+        let parameters = opcode.resolve_parameters(self)?;
+        let reads = match self.trace {
+            Some(_) => opcode.read_addresses(self)?,
+            None => Vec::new(),
+        };
+        let target = match parameters.last() {
+            Some(target) if opcode.op().writes_to_program() => Some(self.resolve_address(target)?),
+            _ => None,
+        };
+        let overwritten = target.map(|address| (address, self.program[address].clone()));
+        let relative_base = self.relative_base.clone();
+        let status = opcode.op().execute(self, &parameters, next_head)?;
+        if status == Some(Status::NeedsInput) {
+            return Ok(status);
+        }
+        let write = target.map(|address| (address, self.program[address].clone()));
+        if let (Some(watchdog), Some((address, value))) = (&mut self.watchdog, &overwritten) {
+            watchdog.note_write(*address, value);
+        }
+        if let Some(journal) = &mut self.journal {
+            journal.push(JournalEntry {
+                cycle: self.cycles,
+                head: self.head,
+                relative_base,
+                overwritten,
+                input: match opcode.op() {
+                    Op::Save => write.clone().map(|(_, value)| value),
+                    _ => None,
+                },
+                output: match &status {
+                    Some(Status::Output(value)) => Some(value.clone()),
+                    _ => None,
+                },
+                halted: status == Some(Status::Halted),
+            });
+        }
+        if let Some(trace) = &mut self.trace {
+            trace.record(&TraceEvent {
+                cycle: self.cycles,
+                address: self.head,
+                opcode: *opcode,
+                operands,
+                parameters,
+                reads,
+                write,
+            });
+        }
+        Ok(status)
+    }
+    // Undoes the most recently journaled instruction, returning false if
+    // there is none. A consumed input is put back at the front of `inputs`
+    // and an output still at the back of `outputs` is retracted; I/O that
+    // went through `io` cannot be taken back.
+    pub fn step_back(&mut self) -> bool {
+        let Some(entry) = self.journal.as_mut().and_then(|journal| journal.pop()) else {
+            return false;
+        };
+        if let Some((address, value)) = entry.overwritten {
+            self.program.set(address, value);
+        }
+        if self.io.is_none() {
+            if let Some(input) = entry.input {
+                self.inputs.push_front(input);
             }
+            if entry.output.is_some() && self.outputs.back() == entry.output.as_ref() {
+                self.outputs.pop_back();
+            }
+        }
+        if entry.halted {
+            self.running = true;
+        }
+        self.head = entry.head;
+        self.relative_base = entry.relative_base;
+        self.cycles = entry.cycle;
+        if let Some(watchdog) = &mut self.watchdog {
+            watchdog.reset();
+        }
+        true
+    }
+    // Like `update`, but gives up after executing `cycles` instructions, in
+    // which case None is returned.
+    pub fn run_for(&mut self, cycles: u64) -> Result<Option<Status<W>>, VmError> {
+        for _ in 0..cycles {
+            match self.step()? {
+                None => return Ok(Some(Status::Halted)),
+                Some(Step {
+                    status: Some(status),
+                    ..
+                }) => return Ok(Some(status)),
+                Some(_) => {}
+            }
+        }
+        Ok(None)
+    }
+    // Runs to completion, treating a request for more input as an error.
+    pub fn run(&mut self) -> Result<(), VmError> {
+        loop {
+            match self.update()? {
+                Status::Halted => return Ok(()),
+                Status::NeedsInput => return Err(VmError::InputUnderflow { head: self.head }),
+                Status::Output(_) => {}
+            }
+        }
+    }
+    pub fn set_io<IO: IntcodeIo<W> + Send + 'static>(&mut self, io: IO) {
+        self.io = Some(Box::new(io));
+    }
+    pub fn set_trace<T: TraceSink<W> + Send + 'static>(&mut self, trace: T) {
+        self.trace = Some(Box::new(trace));
+    }
+    // Takes `&mut self` to mark memory pages as shared, so the snapshot and
+    // the machine copy only the pages they go on to write.
+    pub fn snapshot(&mut self) -> Snapshot<W> {
+        self.program.share_pages();
+        Snapshot {
+            program: self.program.clone(),
+            head: self.head,
+            relative_base: self.relative_base.clone(),
+            running: self.running,
+            cycles: self.cycles,
+            inputs: self.inputs.clone(),
+            outputs: self.outputs.clone(),
+        }
+    }
+    // Rewinds (or fast-forwards) to `snapshot`, keeping the current I/O
+    // handler and trace. The journal no longer applies and is emptied.
+    pub fn restore(&mut self, snapshot: &Snapshot<W>) {
+        self.program = snapshot.program.clone();
+        self.head = snapshot.head;
+        self.relative_base = snapshot.relative_base.clone();
+        self.running = snapshot.running;
+        self.cycles = snapshot.cycles;
+        self.inputs = snapshot.inputs.clone();
+        self.outputs = snapshot.outputs.clone();
+        if let Some(journal) = &mut self.journal {
+            journal.clear();
+        }
+        if let Some(watchdog) = &mut self.watchdog {
+            watchdog.reset();
+        }
+    }
+    // Keeps the last `capacity` instructions so they can be undone.
+    pub fn enable_journal(&mut self, capacity: usize) {
+        self.journal = Some(Journal::new(capacity));
+    }
+    // Fails with `VmError::InfiniteLoop` once the program is caught looping
+    // without I/O, or `VmError::CycleBudget` after `budget` instructions
+    // without I/O.
+    pub fn enable_watchdog(&mut self, budget: Option<u64>) {
+        self.watchdog = Some(Watchdog::new(budget));
+    }
+    pub(crate) fn next_input(&mut self) -> Option<W> {
+        match &mut self.io {
+            Some(io) => io.read(),
+            None => self.inputs.pop_front(),
         }
-        false
+    }
+    pub(crate) fn emit_output(&mut self, value: W) {
+        match &mut self.io {
+            Some(io) => io.write(value),
+            None => self.outputs.push_back(value),
+        }
+    }
+    pub fn push_input(&mut self, value: W) {
+        self.inputs.push_back(value);
+    }
+    pub fn extend_inputs<I: IntoIterator<Item = W>>(&mut self, values: I) {
+        self.inputs.extend(values);
+    }
+    // Removes and returns the oldest output not yet taken.
+    pub fn pop_output(&mut self) -> Option<W> {
+        self.outputs.pop_front()
+    }
+    pub fn drain_outputs(&mut self) -> Vec<W> {
+        self.outputs.drain(..).collect()
+    }
+    pub fn resolve_address(&self, value: &W) -> Result<usize, VmError> {
+        self.program
+            .resolve(value)
+            .ok_or_else(|| VmError::BadAddress {
+                head: self.head,
+                value: value.to_string(),
+            })
+    }
+    pub fn read(&self, address: &W) -> Result<W, VmError> {
+        Ok(self.program[self.resolve_address(address)?].clone())
+    }
+    pub fn write(&mut self, address: &W, value: W) -> Result<(), VmError> {
+        let address = self.resolve_address(address)?;
+        self.program.set(address, value);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::intcode::error::VmError;
+    use crate::intcode::io::{ChannelIo, FnIo};
+    use crate::intcode::opcode::Op;
+    use crate::intcode::program::{ProgramState, Status};
+    use std::sync::mpsc::channel;
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+
+    fn state(program: Vec<i64>, inputs: Vec<i64>) -> ProgramState {
+        let mut program_state = ProgramState::new(program);
+        program_state.extend_inputs(inputs);
+        program_state
+    }
+    fn run(program: Vec<i64>, inputs: Vec<i64>) -> ProgramState {
+        let mut program_state = state(program, inputs);
+        program_state.run().unwrap();
+        program_state
+    }
+    #[test]
+    fn test_relative_read() {
+        let result = run(vec![109, 7, 204, -1, 99, 0, 42], Vec::new());
+        assert_eq!(result.relative_base, 7);
+        assert_eq!(result.outputs, vec![42]);
+    }
+    #[test]
+    fn test_relative_save() {
+        let result = run(vec![109, 8, 203, 0, 204, 0, 99, 0, 0], vec![123]);
+        assert_eq!(result.program[8], 123);
+        assert_eq!(result.outputs, vec![123]);
+    }
+    #[test]
+    fn test_relative_write_target() {
+        let result = run(vec![109, 9, 21101, 2, 3, 0, 204, 0, 99, 0], Vec::new());
+        assert_eq!(result.program[9], 5);
+        assert_eq!(result.outputs, vec![5]);
+    }
+    #[test]
+    fn test_adjust_relative_base_negative() {
+        let result = run(vec![109, 10, 209, -3, 204, -1, 99, -4], Vec::new());
+        assert_eq!(result.relative_base, 6);
+        assert_eq!(result.outputs, vec![-1]);
+    }
+    #[test]
+    fn test_large_output() {
+        let result = run(vec![104, 1125899906842624, 99], Vec::new());
+        assert_eq!(result.outputs, vec![1125899906842624]);
+    }
+    #[test]
+    fn test_large_multiplication() {
+        let result = run(vec![1102, 34915192, 34915192, 7, 4, 7, 99, 0], Vec::new());
+        assert_eq!(result.outputs, vec![1219070632396864]);
+    }
+    #[test]
+    fn test_overflow_is_detected() {
+        let mut program_state = state(vec![1102, i64::MAX, 2, 5, 99, 0], Vec::new());
+        assert_eq!(program_state.run(), Err(VmError::Overflow { head: 0 }));
+    }
+    #[cfg(feature = "bigint")]
+    #[test]
+    fn test_bigint_multiplication() {
+        use num_bigint::BigInt;
+        let big = BigInt::from(i64::MAX);
+        let mut program_state = ProgramState::new(
+            [1102, 0, 0, 7, 4, 7, 99, 0]
+                .into_iter()
+                .map(BigInt::from)
+                .collect(),
+        );
+        program_state.program[1] = big.clone();
+        program_state.program[2] = big.clone();
+        program_state.run().unwrap();
+        assert_eq!(program_state.outputs, vec![&big * &big]);
+    }
+    #[test]
+    fn test_quine_grows_memory() {
+        let program = vec![
+            109, 1, 204, -1, 1001, 100, 1, 100, 1008, 100, 16, 101, 1006, 101, 0, 99,
+        ];
+        let result = run(program.clone(), Vec::new());
+        assert_eq!(result.outputs, program);
+    }
+    #[test]
+    fn test_negative_address_is_an_error() {
+        let mut program_state = state(vec![1101, 1, 1, 7, 4, -1, 99, 0], Vec::new());
+        assert_eq!(
+            program_state.run(),
+            Err(VmError::BadAddress {
+                head: 4,
+                value: "-1".to_string()
+            })
+        );
+    }
+    #[test]
+    fn test_unknown_opcode_is_an_error() {
+        let mut program_state = state(vec![1101, 1, 1, 5, 42, 0], Vec::new());
+        assert_eq!(
+            program_state.run(),
+            Err(VmError::UnknownOpcode {
+                head: 4,
+                code: "42".to_string()
+            })
+        );
+        let mut program_state = state(vec![-1], Vec::new());
+        assert_eq!(
+            program_state.run(),
+            Err(VmError::UnknownOpcode {
+                head: 0,
+                code: "-1".to_string()
+            })
+        );
+    }
+    #[test]
+    fn test_bad_parameter_mode_is_an_error() {
+        let mut program_state = state(vec![30001, 0, 0, 0, 99], Vec::new());
+        assert_eq!(
+            program_state.run(),
+            Err(VmError::BadParameterMode {
+                head: 0,
+                parameter: 3,
+                mode: 3
+            })
+        );
+    }
+    #[test]
+    fn test_immediate_write_is_an_error() {
+        let mut program_state = state(vec![11101, 1, 1, 0, 99], Vec::new());
+        assert_eq!(
+            program_state.run(),
+            Err(VmError::ImmediateWrite { head: 0 })
+        );
+    }
+    #[test]
+    fn test_input_underflow_is_an_error() {
+        let mut program_state = state(vec![104, 1, 3, 0, 99], Vec::new());
+        assert_eq!(
+            program_state.run(),
+            Err(VmError::InputUnderflow { head: 2 })
+        );
+        assert_eq!(program_state.outputs, vec![1]);
+    }
+    #[test]
+    fn test_pause_and_resume() {
+        let mut program_state = state(vec![3, 9, 1001, 9, 1, 9, 4, 9, 99, 0], Vec::new());
+        assert_eq!(program_state.update(), Ok(Status::NeedsInput));
+        assert_eq!(program_state.head, 0);
+        assert_eq!(program_state.update(), Ok(Status::NeedsInput));
+        program_state.push_input(41);
+        assert_eq!(program_state.update(), Ok(Status::Output(42)));
+        assert_eq!(program_state.update(), Ok(Status::Halted));
+        assert!(!program_state.running);
+        assert_eq!(program_state.update(), Ok(Status::Halted));
+        assert_eq!(program_state.outputs, vec![42]);
+    }
+    #[test]
+    fn test_yields_each_output() {
+        let mut program_state = state(vec![104, 1, 104, 2, 99], Vec::new());
+        assert_eq!(program_state.update(), Ok(Status::Output(1)));
+        assert_eq!(program_state.update(), Ok(Status::Output(2)));
+        assert_eq!(program_state.update(), Ok(Status::Halted));
+    }
+    #[test]
+    fn test_inputs_are_first_in_first_out() {
+        let mut program_state = state(vec![3, 11, 3, 12, 4, 11, 4, 12, 99, 0, 0, 0, 0], Vec::new());
+        program_state.push_input(1);
+        program_state.extend_inputs([2, 3]);
+        program_state.run().unwrap();
+        assert_eq!(program_state.inputs, vec![3]);
+        assert_eq!(program_state.pop_output(), Some(1));
+        assert_eq!(program_state.drain_outputs(), vec![2]);
+        assert_eq!(program_state.pop_output(), None);
+    }
+    #[test]
+    fn test_closure_io() {
+        let written = Arc::new(Mutex::new(Vec::new()));
+        let sink = Arc::clone(&written);
+        let mut program_state = state(vec![3, 9, 1002, 9, 3, 9, 4, 9, 99, 0], vec![100]);
+        program_state.set_io(FnIo::new(
+            || Some(5),
+            move |value| sink.lock().unwrap().push(value),
+        ));
+        program_state.run().unwrap();
+        assert_eq!(*written.lock().unwrap(), vec![15]);
+        assert_eq!(program_state.inputs, vec![100]);
+        assert!(program_state.outputs.is_empty());
+    }
+    #[test]
+    fn test_channel_io() {
+        let (input_sender, input_receiver) = channel();
+        let (output_sender, output_receiver) = channel();
+        let mut program_state = state(vec![3, 9, 1002, 9, 3, 9, 4, 9, 99, 0], Vec::new());
+        program_state.set_io(ChannelIo::new(input_receiver, output_sender));
+        let handle = thread::spawn(move || program_state.run());
+        input_sender.send(7).unwrap();
+        assert_eq!(output_receiver.recv(), Ok(21));
+        assert_eq!(handle.join().unwrap(), Ok(()));
+    }
+    #[test]
+    fn test_step() {
+        let mut program_state = state(vec![1101, 2, 3, 7, 3, 7, 99, 0], Vec::new());
+        let step = program_state.step().unwrap().unwrap();
+        assert_eq!(
+            (step.address, step.opcode.op(), step.status),
+            (0, Op::Add, None)
+        );
+        assert_eq!(program_state.program[7], 5);
+        let step = program_state.step().unwrap().unwrap();
+        assert_eq!(
+            (step.address, step.opcode.op(), step.status),
+            (4, Op::Save, Some(Status::NeedsInput))
+        );
+        assert_eq!((program_state.head, program_state.cycles), (4, 1));
+        program_state.push_input(9);
+        let step = program_state.step().unwrap().unwrap();
+        assert_eq!((step.address, step.status), (4, None));
+        let step = program_state.step().unwrap().unwrap();
+        assert_eq!(
+            (step.address, step.opcode.op(), step.status),
+            (6, Op::Halt, Some(Status::Halted))
+        );
+        assert_eq!(program_state.step(), Ok(None));
+        assert_eq!(program_state.cycles, 3);
+    }
+    #[test]
+    fn test_run_for_bounds_infinite_loop() {
+        let mut program_state = state(vec![1105, 1, 0], Vec::new());
+        assert_eq!(program_state.run_for(1000), Ok(None));
+        assert_eq!(program_state.cycles, 1000);
+        let mut program_state = state(vec![104, 3, 99], Vec::new());
+        assert_eq!(program_state.run_for(1000), Ok(Some(Status::Output(3))));
+        assert_eq!(program_state.run_for(1000), Ok(Some(Status::Halted)));
+        assert_eq!(program_state.run_for(1000), Ok(Some(Status::Halted)));
+        assert_eq!(program_state.cycles, 2);
+    }
+    #[test]
+    fn test_jump_to_own_address() {
+        let mut program_state = state(vec![1105, 1, 0, 99], Vec::new());
+        assert_eq!(program_state.run_for(10), Ok(None));
+        assert_eq!(program_state.head, 0);
     }
 }
@@ -0,0 +1,231 @@
+use crate::intcode::loader::LoadError;
+use crate::intcode::memory::Memory;
+use crate::intcode::program::ProgramState;
+use crate::intcode::word::Word;
+use std::collections::VecDeque;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::str::FromStr;
+
+const HEADER: &str = "intcode-snapshot 1";
+
+// A paused machine. Memory pages are shared with the machine it was taken
+// from until either side writes to them, so taking one is cheap and many
+// machines can be resumed from the same snapshot. I/O handlers, traces and
+// journals are not part of a snapshot.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Snapshot<W: Word = i64> {
+    pub program: Memory<W>,
+    pub head: usize,
+    pub relative_base: W,
+    pub running: bool,
+    pub cycles: u64,
+    pub inputs: VecDeque<W>,
+    pub outputs: VecDeque<W>,
+}
+impl<W: Word> Snapshot<W> {
+    // A new machine starting from this snapshot.
+    pub fn resume(&self) -> ProgramState<W> {
+        let mut program_state = ProgramState::new(Vec::new());
+        program_state.restore(self);
+        program_state
+    }
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        fs::write(path, self.to_string())
+    }
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, LoadError> {
+        fs::read_to_string(path)?.parse()
+    }
+}
+
+fn join<'a, W: Word>(values: impl Iterator<Item = &'a W>) -> String {
+    let values: Vec<String> = values.map(|value| value.to_string()).collect();
+    values.join(",")
+}
+// A line-based text format: a header followed by one `key value` line per
+// field, with lists comma-separated and far-off memory cells as
+// `address=value`.
+impl<W: Word> fmt::Display for Snapshot<W> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{}", HEADER)?;
+        writeln!(f, "head {}", self.head)?;
+        writeln!(f, "relative_base {}", self.relative_base)?;
+        writeln!(f, "running {}", self.running)?;
+        writeln!(f, "cycles {}", self.cycles)?;
+        writeln!(f, "inputs {}", join(self.inputs.iter()))?;
+        writeln!(f, "outputs {}", join(self.outputs.iter()))?;
+        match self.program.limit() {
+            Some(limit) => writeln!(f, "limit {}", limit)?,
+            None => writeln!(f, "limit none")?,
+        }
+        writeln!(f, "memory {}", join(self.program.dense_cells()))?;
+        let sparse: Vec<String> = self
+            .program
+            .sparse_cells()
+            .iter()
+            .map(|(address, value)| format!("{}={}", address, value))
+            .collect();
+        writeln!(f, "sparse {}", sparse.join(","))
+    }
+}
+
+fn parse_value<T: FromStr>(line: usize, token: &str) -> Result<T, LoadError> {
+    token.parse().map_err(|_| LoadError::BadSnapshot {
+        line,
+        reason: format!("invalid value {:?}", token),
+    })
+}
+fn parse_list<W: Word>(line: usize, value: &str) -> Result<Vec<W>, LoadError> {
+    value
+        .split(',')
+        .filter(|token| !token.is_empty())
+        .map(|token| parse_value(line, token))
+        .collect()
+}
+impl<W: Word> FromStr for Snapshot<W> {
+    type Err = LoadError;
+    fn from_str(contents: &str) -> Result<Self, LoadError> {
+        let mut lines = contents
+            .lines()
+            .enumerate()
+            .map(|(index, line)| (index + 1, line));
+        if lines.next().map(|(_, line)| line.trim()) != Some(HEADER) {
+            return Err(LoadError::BadSnapshot {
+                line: 1,
+                reason: "not an Intcode snapshot".to_string(),
+            });
+        }
+        let mut snapshot = ProgramState::new(Vec::new()).snapshot();
+        let mut sparse = Vec::new();
+        for (line, text) in lines {
+            let (key, value) = text.split_once(' ').unwrap_or((text, ""));
+            let value = value.trim();
+            match key {
+                "head" => snapshot.head = parse_value(line, value)?,
+                "relative_base" => snapshot.relative_base = parse_value(line, value)?,
+                "running" => snapshot.running = parse_value(line, value)?,
+                "cycles" => snapshot.cycles = parse_value(line, value)?,
+                "inputs" => snapshot.inputs = parse_list(line, value)?.into(),
+                "outputs" => snapshot.outputs = parse_list(line, value)?.into(),
+                "limit" if value == "none" => snapshot.program.set_limit(None),
+                "limit" => snapshot.program.set_limit(Some(parse_value(line, value)?)),
+                "memory" => {
+                    let limit = snapshot.program.limit();
+                    snapshot.program = Memory::new(parse_list(line, value)?);
+                    snapshot.program.set_limit(limit);
+                }
+                "sparse" => {
+                    for cell in value.split(',').filter(|cell| !cell.is_empty()) {
+                        let Some((address, value)) = cell.split_once('=') else {
+                            return Err(LoadError::BadSnapshot {
+                                line,
+                                reason: format!("expected address=value, found {:?}", cell),
+                            });
+                        };
+                        sparse.push((parse_value(line, address)?, parse_value(line, value)?));
+                    }
+                }
+                "" => {}
+                _ => {
+                    return Err(LoadError::BadSnapshot {
+                        line,
+                        reason: format!("unknown field {:?}", key),
+                    })
+                }
+            }
+        }
+        for (address, value) in sparse {
+            snapshot.program.set(address, value);
+        }
+        snapshot.program.share_pages();
+        Ok(snapshot)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::intcode::asm::{assemble, COUNTDOWN};
+    use crate::intcode::loader::{LoadError, ProgramBuilder};
+    use crate::intcode::program::Status;
+    use crate::intcode::snapshot::Snapshot;
+
+    #[test]
+    fn test_fork_from_snapshot() {
+        let mut program_state = ProgramBuilder::new(assemble(COUNTDOWN).unwrap()).build();
+        assert_eq!(program_state.update(), Ok(Status::NeedsInput));
+        let snapshot = program_state.snapshot();
+        for start in [2, 5] {
+            let mut fork = snapshot.resume();
+            fork.push_input(start);
+            fork.run().unwrap();
+            assert_eq!(fork.drain_outputs(), (1..=start).rev().collect::<Vec<_>>());
+        }
+        // The forks wrote to their own copies of the counter.
+        assert_eq!(snapshot.program[12], 0);
+        program_state.push_input(1);
+        program_state.run().unwrap();
+        assert_eq!(program_state.drain_outputs(), vec![1]);
+    }
+    #[test]
+    fn test_restore() {
+        let mut program_state = ProgramBuilder::new(assemble(COUNTDOWN).unwrap())
+            .input(3)
+            .build();
+        assert_eq!(program_state.update(), Ok(Status::Output(3)));
+        let snapshot = program_state.snapshot();
+        program_state.run().unwrap();
+        assert!(!program_state.running);
+        program_state.restore(&snapshot);
+        assert!(program_state.running);
+        assert_eq!(program_state.cycles, 2);
+        assert_eq!(program_state.outputs, vec![3]);
+        program_state.run().unwrap();
+        assert_eq!(program_state.drain_outputs(), vec![3, 2, 1]);
+    }
+    #[test]
+    fn test_text_round_trip() {
+        let mut program_state = ProgramBuilder::new(vec![109, -4, 3, 0, 104, 7, 99])
+            .inputs([5, 6])
+            .memory_limit(1 << 50)
+            .build();
+        program_state.program.set(1 << 40, -9);
+        program_state.update().unwrap();
+        let snapshot = program_state.snapshot();
+        let text = snapshot.to_string();
+        assert!(text.contains("\nrelative_base -4\n"));
+        assert!(text.contains("\ninputs 6\n"));
+        assert!(text.contains("\nsparse 1099511627776=-9\n"));
+        let parsed: Snapshot = text.parse().unwrap();
+        assert_eq!(parsed, snapshot);
+        assert_eq!(parsed.program.limit(), Some(1 << 50));
+    }
+    #[test]
+    fn test_file_round_trip() {
+        let mut program_state = ProgramBuilder::new(assemble(COUNTDOWN).unwrap())
+            .input(2)
+            .build();
+        program_state.update().unwrap();
+        let path = std::env::temp_dir().join(format!("snapshot-{}.txt", std::process::id()));
+        program_state.snapshot().save(&path).unwrap();
+        let mut resumed = Snapshot::<i64>::load(&path).unwrap().resume();
+        std::fs::remove_file(&path).unwrap();
+        resumed.run().unwrap();
+        assert_eq!(resumed.drain_outputs(), vec![2, 1]);
+    }
+    #[test]
+    fn test_parse_errors() {
+        assert!(matches!(
+            "1,2,3".parse::<Snapshot>(),
+            Err(LoadError::BadSnapshot { line: 1, .. })
+        ));
+        match "intcode-snapshot 1\nhead 0\ncycles x\n".parse::<Snapshot>() {
+            Err(LoadError::BadSnapshot { line, reason }) => {
+                assert_eq!(line, 3);
+                assert_eq!(reason, "invalid value \"x\"");
+            }
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+}
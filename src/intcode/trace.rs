@@ -0,0 +1,207 @@
+use crate::intcode::disasm::Line;
+use crate::intcode::opcode::OpCode;
+use crate::intcode::word::Word;
+use std::fmt;
+use std::io::Write;
+use std::sync::{Arc, Mutex};
+
+// One executed instruction, as handed to a `TraceSink`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TraceEvent<W: Word = i64> {
+    // Instructions executed before this one.
+    pub cycle: u64,
+    pub address: usize,
+    pub opcode: OpCode,
+    // The words following the instruction, as they were before it ran.
+    pub operands: Vec<W>,
+    // What each operand resolved to: the value read, or for the parameter
+    // being written to, the address.
+    pub parameters: Vec<W>,
+    // The addresses parameters were read from.
+    pub reads: Vec<usize>,
+    // The address and new value of the cell the instruction wrote, if any.
+    pub write: Option<(usize, W)>,
+}
+impl<W: Word> TraceEvent<W> {
+    // A single line of JSON with no dependencies on a serialisation library;
+    // words are written as bare numbers so big integers survive intact.
+    pub fn to_json(&self) -> String {
+        let join = |values: &[W]| {
+            let values: Vec<String> = values.iter().map(|value| value.to_string()).collect();
+            values.join(",")
+        };
+        let modes: Vec<String> = self
+            .opcode
+            .param_modes()
+            .iter()
+            .map(|mode| mode.digit().to_string())
+            .collect();
+        let reads: Vec<String> = self
+            .reads
+            .iter()
+            .map(|address| address.to_string())
+            .collect();
+        let write = match &self.write {
+            Some((address, value)) => format!("{{\"address\":{},\"value\":{}}}", address, value),
+            None => "null".to_string(),
+        };
+        format!(
+            "{{\"cycle\":{},\"address\":{},\"op\":\"{}\",\"modes\":[{}],\"operands\":[{}],\"parameters\":[{}],\"reads\":[{}],\"write\":{}}}",
+            self.cycle,
+            self.address,
+            self.opcode.op().mnemonic(),
+            modes.join(","),
+            join(&self.operands),
+            join(&self.parameters),
+            reads.join(","),
+            write
+        )
+    }
+}
+// The disassembled instruction followed by what it resolved and wrote, e.g.
+// `     0     0: MUL [4], #3, [4] | 33, 3, 4 | [4] = 99`.
+impl<W: Word> fmt::Display for TraceEvent<W> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let line = Line::Instruction {
+            address: self.address,
+            opcode: self.opcode,
+            operands: self.operands.clone(),
+        };
+        write!(f, "{:>6} {}", self.cycle, line)?;
+        if !self.parameters.is_empty() {
+            let parameters: Vec<String> = self.parameters.iter().map(|p| p.to_string()).collect();
+            write!(f, " | {}", parameters.join(", "))?;
+        }
+        if let Some((address, value)) = &self.write {
+            write!(f, " | [{}] = {}", address, value)?;
+        }
+        Ok(())
+    }
+}
+
+// Receives every instruction a traced `ProgramState` executes.
+pub trait TraceSink<W: Word> {
+    fn record(&mut self, event: &TraceEvent<W>);
+}
+impl<W: Word> fmt::Debug for dyn TraceSink<W> + Send {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("TraceSink")
+    }
+}
+impl<W: Word> TraceSink<W> for Vec<TraceEvent<W>> {
+    fn record(&mut self, event: &TraceEvent<W>) {
+        self.push(event.clone());
+    }
+}
+// Lets the caller keep a handle on a sink after giving it to the VM.
+impl<W: Word, S: TraceSink<W>> TraceSink<W> for Arc<Mutex<S>> {
+    fn record(&mut self, event: &TraceEvent<W>) {
+        self.lock().unwrap().record(event);
+    }
+}
+
+// Writes one line per instruction in the format of `TraceEvent`'s Display.
+pub struct TextTrace<O: Write> {
+    out: O,
+}
+impl<O: Write> TextTrace<O> {
+    pub fn new(out: O) -> Self {
+        TextTrace { out }
+    }
+}
+impl<W: Word, O: Write> TraceSink<W> for TextTrace<O> {
+    fn record(&mut self, event: &TraceEvent<W>) {
+        writeln!(self.out, "{}", event).expect("Should have been able to write the trace");
+    }
+}
+
+// Writes one JSON object per instruction, one per line.
+pub struct JsonTrace<O: Write> {
+    out: O,
+}
+impl<O: Write> JsonTrace<O> {
+    pub fn new(out: O) -> Self {
+        JsonTrace { out }
+    }
+}
+impl<W: Word, O: Write> TraceSink<W> for JsonTrace<O> {
+    fn record(&mut self, event: &TraceEvent<W>) {
+        writeln!(self.out, "{}", event.to_json())
+            .expect("Should have been able to write the trace");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::intcode::loader::ProgramBuilder;
+    use crate::intcode::trace::{JsonTrace, TextTrace, TraceEvent};
+    use std::sync::{Arc, Mutex};
+
+    fn traced(image: Vec<i64>, inputs: Vec<i64>) -> Vec<TraceEvent> {
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let mut program_state = ProgramBuilder::new(image)
+            .inputs(inputs)
+            .trace(events.clone())
+            .build();
+        program_state.run().unwrap();
+        let events = events.lock().unwrap().clone();
+        events
+    }
+    #[test]
+    fn test_events() {
+        let events = traced(vec![3, 9, 1002, 9, 3, 9, 4, 9, 99, 0], vec![11]);
+        assert_eq!(events.len(), 4);
+        assert_eq!(
+            events.iter().map(|event| event.address).collect::<Vec<_>>(),
+            vec![0, 2, 6, 8]
+        );
+        assert_eq!(events[0].write, Some((9, 11)));
+        assert_eq!(events[1].operands, vec![9, 3, 9]);
+        assert_eq!(events[1].parameters, vec![11, 3, 9]);
+        assert_eq!(events[1].reads, vec![9]);
+        assert_eq!(events[1].write, Some((9, 33)));
+        assert_eq!(events[2].parameters, vec![33]);
+        assert_eq!(events[2].write, None);
+        assert_eq!(events[3].cycle, 3);
+    }
+    #[test]
+    fn test_relative_write_is_resolved() {
+        let events = traced(vec![109, 9, 21101, 2, 3, 1, 99, 0, 0, 0, 0], Vec::new());
+        assert_eq!(events[1].operands, vec![2, 3, 1]);
+        assert_eq!(events[1].parameters, vec![2, 3, 10]);
+        assert!(events[1].reads.is_empty());
+        assert_eq!(events[1].write, Some((10, 5)));
+    }
+    #[test]
+    fn test_text_format() {
+        let trace = Arc::new(Mutex::new(TextTrace::new(Vec::new())));
+        let mut program_state = ProgramBuilder::new(vec![1002, 4, 3, 4, 33])
+            .trace(trace.clone())
+            .build();
+        program_state.run().unwrap();
+        assert_eq!(
+            String::from_utf8(trace.lock().unwrap().out.clone()).unwrap(),
+            "     0     0: MUL [4], #3, [4] | 33, 3, 4 | [4] = 99\n     1     4: HLT\n"
+        );
+    }
+    #[test]
+    fn test_json_format() {
+        let trace = Arc::new(Mutex::new(JsonTrace::new(Vec::new())));
+        let mut program_state = ProgramBuilder::new(vec![104, -7, 99])
+            .trace(trace.clone())
+            .build();
+        program_state.run().unwrap();
+        let lines: Vec<String> = String::from_utf8(trace.lock().unwrap().out.clone())
+            .unwrap()
+            .lines()
+            .map(|line| line.to_string())
+            .collect();
+        assert_eq!(
+            lines,
+            vec![
+                r#"{"cycle":0,"address":0,"op":"OUT","modes":[1],"operands":[-7],"parameters":[-7],"reads":[],"write":null}"#,
+                r#"{"cycle":1,"address":2,"op":"HLT","modes":[],"operands":[],"parameters":[],"reads":[],"write":null}"#
+            ]
+        );
+    }
+}
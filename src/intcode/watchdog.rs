@@ -0,0 +1,219 @@
+use crate::intcode::error::VmError;
+use crate::intcode::memory::Memory;
+use crate::intcode::word::Word;
+use std::collections::HashMap;
+
+// Catches a machine that has stopped doing I/O and never will. It takes
+// checkpoints of the machine's state at doubling intervals (Brent's cycle
+// detection) and reports a loop as soon as the machine is back in a
+// checkpointed state: the same head and relative base, with every cell
+// written since holding its checkpoint value again. Cells nobody wrote cannot
+// have changed, so only those the loop touches are compared.
+//
+// I/O starts everything afresh, since the program may be waiting on the
+// outside world.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Watchdog<W: Word = i64> {
+    // Most instructions allowed to run without any I/O.
+    budget: Option<u64>,
+    // Instructions since the last I/O.
+    quiet: u64,
+    checkpoint: Option<Checkpoint<W>>,
+    // Instructions to run from one checkpoint before taking the next.
+    interval: u64,
+}
+#[derive(Debug, Clone, PartialEq)]
+struct Checkpoint<W: Word> {
+    head: usize,
+    relative_base: W,
+    // What each cell written since the checkpoint held at the time.
+    written: HashMap<usize, W>,
+    // Instructions executed since the checkpoint, and the lowest and highest
+    // of their addresses.
+    elapsed: u64,
+    lowest: usize,
+    highest: usize,
+}
+impl<W: Word> Watchdog<W> {
+    pub fn new(budget: Option<u64>) -> Self {
+        Watchdog {
+            budget,
+            quiet: 0,
+            checkpoint: None,
+            interval: 1,
+        }
+    }
+    // Forgets everything seen so far, e.g. after the machine is rewound.
+    pub fn reset(&mut self) {
+        self.quiet = 0;
+        self.checkpoint = None;
+        self.interval = 1;
+    }
+    // Called with the old value of each cell before an instruction writes it.
+    pub fn note_write(&mut self, address: usize, value: &W) {
+        if let Some(checkpoint) = &mut self.checkpoint {
+            checkpoint
+                .written
+                .entry(address)
+                .or_insert_with(|| value.clone());
+        }
+    }
+    // Called after each instruction executes, with the state it left behind.
+    pub fn check(
+        &mut self,
+        address: usize,
+        io: bool,
+        head: usize,
+        relative_base: &W,
+        memory: &Memory<W>,
+    ) -> Result<(), VmError> {
+        if io {
+            self.reset();
+        } else {
+            self.quiet += 1;
+        }
+        let Some(checkpoint) = &mut self.checkpoint else {
+            self.take_checkpoint(head, relative_base);
+            return Ok(());
+        };
+        checkpoint.elapsed += 1;
+        checkpoint.lowest = checkpoint.lowest.min(address);
+        checkpoint.highest = checkpoint.highest.max(address);
+        if head == checkpoint.head
+            && *relative_base == checkpoint.relative_base
+            && checkpoint
+                .written
+                .iter()
+                .all(|(address, value)| memory[*address] == *value)
+        {
+            return Err(VmError::InfiniteLoop {
+                head,
+                period: checkpoint.elapsed,
+                start: checkpoint.lowest,
+                end: checkpoint.highest,
+            });
+        }
+        if let Some(budget) = self.budget.filter(|budget| self.quiet > *budget) {
+            return Err(VmError::CycleBudget {
+                head,
+                budget,
+                start: checkpoint.lowest,
+                end: checkpoint.highest,
+            });
+        }
+        if checkpoint.elapsed == self.interval {
+            self.interval *= 2;
+            self.take_checkpoint(head, relative_base);
+        }
+        Ok(())
+    }
+    fn take_checkpoint(&mut self, head: usize, relative_base: &W) {
+        self.checkpoint = Some(Checkpoint {
+            head,
+            relative_base: relative_base.clone(),
+            written: HashMap::new(),
+            elapsed: 0,
+            lowest: usize::MAX,
+            highest: 0,
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::intcode::asm::assemble;
+    use crate::intcode::engine::Engine;
+    use crate::intcode::error::VmError;
+    use crate::intcode::loader::ProgramBuilder;
+    use crate::intcode::program::{ProgramState, Status};
+
+    fn watched(source: &str, budget: Option<u64>) -> ProgramState {
+        ProgramBuilder::new(assemble(source).unwrap())
+            .watchdog(budget)
+            .build()
+    }
+
+    #[test]
+    fn test_tight_loop() {
+        let mut program_state = watched("loop: JT #1, #loop", None);
+        assert_eq!(
+            program_state.run(),
+            Err(VmError::InfiniteLoop {
+                head: 0,
+                period: 1,
+                start: 0,
+                end: 0
+            })
+        );
+        let mut engine = Engine::new(watched("loop: JT #1, #loop", None));
+        assert!(matches!(engine.update(), Err(VmError::InfiniteLoop { .. })));
+    }
+    #[test]
+    fn test_loop_through_memory() {
+        // The flag flips each time round, so the state only repeats every
+        // other pass.
+        let mut program_state = watched(
+            "
+            loop:   EQ [flag], #0, [flag]
+                    JT #1, #loop
+            flag:   data 0",
+            None,
+        );
+        let error = program_state.run().unwrap_err();
+        assert_eq!(
+            error,
+            VmError::InfiniteLoop {
+                head: 0,
+                period: 4,
+                start: 0,
+                end: 4
+            }
+        );
+        assert_eq!(
+            error.to_string(),
+            "infinite loop at 0: instructions 0..=4 repeat every 4 cycles without I/O"
+        );
+    }
+    #[test]
+    fn test_cycle_budget() {
+        // The counter never comes back round, so only the budget stops it.
+        let mut program_state = watched(
+            "
+                    IN [n]
+            loop:   ADD [n], #1, [n]
+                    JT #1, #loop
+            n:      data 0",
+            Some(1000),
+        );
+        program_state.push_input(5);
+        assert_eq!(
+            program_state.run(),
+            Err(VmError::CycleBudget {
+                head: 6,
+                budget: 1000,
+                start: 2,
+                end: 6
+            })
+        );
+        assert_eq!(program_state.cycles, 1002);
+    }
+    #[test]
+    fn test_terminating_loops() {
+        let mut program_state = watched(
+            "
+            loop:   ADD [n], #-1, [n]
+                    JT [n], #loop
+                    OUT #7
+                    HLT
+            n:      data 50",
+            None,
+        );
+        assert_eq!(program_state.run(), Ok(()));
+        assert_eq!(program_state.drain_outputs(), vec![7]);
+        // I/O resets the budget, so a program may talk forever.
+        let mut program_state = watched("loop: OUT #1\nJT #1, #loop", Some(3));
+        for _ in 0..100 {
+            assert_eq!(program_state.update(), Ok(Status::Output(1)));
+        }
+    }
+}
@@ -0,0 +1,74 @@
+use std::fmt::{Debug, Display};
+use std::str::FromStr;
+
+// The value type stored in each Intcode memory cell. Arithmetic is checked so
+// that programs multiplying large constants fail loudly instead of wrapping.
+pub trait Word:
+    Clone + Debug + Display + Default + PartialEq + PartialOrd + FromStr + Send + Sync + 'static
+{
+    // None when the value does not fit, as with `to_i64`.
+    fn from_i64(value: i64) -> Option<Self>;
+    fn to_i64(&self) -> Option<i64>;
+    fn checked_add(&self, other: &Self) -> Option<Self>;
+    fn checked_mul(&self, other: &Self) -> Option<Self>;
+    fn is_zero(&self) -> bool;
+    fn to_address(&self) -> Option<usize> {
+        self.to_i64().and_then(|value| usize::try_from(value).ok())
+    }
+}
+
+macro_rules! impl_word_for_primitive {
+    ($($t:ty),*) => {
+        $(
+            impl Word for $t {
+                fn from_i64(value: i64) -> Option<Self> {
+                    <$t>::try_from(value).ok()
+                }
+                fn to_i64(&self) -> Option<i64> {
+                    i64::try_from(*self).ok()
+                }
+                fn checked_add(&self, other: &Self) -> Option<Self> {
+                    <$t>::checked_add(*self, *other)
+                }
+                fn checked_mul(&self, other: &Self) -> Option<Self> {
+                    <$t>::checked_mul(*self, *other)
+                }
+                fn is_zero(&self) -> bool {
+                    *self == 0
+                }
+            }
+        )*
+    };
+}
+impl_word_for_primitive!(i32, i64, i128);
+
+#[cfg(feature = "bigint")]
+impl Word for num_bigint::BigInt {
+    fn from_i64(value: i64) -> Option<Self> {
+        Some(num_bigint::BigInt::from(value))
+    }
+    fn to_i64(&self) -> Option<i64> {
+        i64::try_from(self).ok()
+    }
+    fn checked_add(&self, other: &Self) -> Option<Self> {
+        Some(self + other)
+    }
+    fn checked_mul(&self, other: &Self) -> Option<Self> {
+        Some(self * other)
+    }
+    fn is_zero(&self) -> bool {
+        self.sign() == num_bigint::Sign::NoSign
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::intcode::word::Word;
+
+    #[test]
+    fn test_narrow_words_do_not_truncate() {
+        assert_eq!(i32::from_i64(-5), Some(-5));
+        assert_eq!(i32::from_i64(1 << 40), None);
+        assert_eq!(i64::from_i64(1 << 40), Some(1 << 40));
+    }
+}
@@ -14,13 +14,33 @@ use crate::day6::day6;
 pub mod day7;
 use crate::day7::day7;
 pub mod intcode;
+use crate::intcode::aot::translate;
+use crate::intcode::ascii::ascii;
+use crate::intcode::bench::bench;
+use crate::intcode::cfg::cfg;
+use crate::intcode::debugger::debug;
+use crate::intcode::disasm::disasm;
+use crate::intcode::profile::profile;
 
 use std::env;
 
 fn main() {
     let args: Vec<String> = env::args().collect();
 
-    let day = &args[1];
+    // Tool subcommands take the path of an Intcode program.
+    match args[1].as_str() {
+        "ascii" => ascii(args[2].clone(), &args[3..]),
+        "bench" => bench(),
+        "cfg" => cfg(args[2].clone()),
+        "debug" => debug(args[2].clone(), &args[3..]),
+        "disasm" => disasm(args[2].clone()),
+        "profile" => profile(args[2].clone()),
+        "translate" => translate(args[2].clone()),
+        day => run_day(day),
+    }
+}
+
+fn run_day(day: &str) {
     let file_path = format!("inputs/day{}-input.txt", day);
 
     match day.parse().unwrap() {